@@ -0,0 +1,63 @@
+// Compares how much memory the blocks of generated chunks take with the palette
+// against the two arrays of usizes chunks used to have, with the default chunk width.
+// Run with: cargo bench --bench chunk_memory
+#![allow(clippy::needless_return)]
+
+use std::{mem::size_of_val, time::Instant};
+
+use macroquad::prelude::IVec2;
+
+use mijocraft_macroquad::{
+    block_registry::BlockRegistry,
+    block_storage::PalettedBlocks,
+    coordinates::ChunkPos,
+    world_config::WorldConfig,
+    world_generator::WorldGenerator,
+};
+
+const SEED: u64 = 1234;
+// 64 chunks wide, from the caves up to the sky
+const AREA_MIN: ChunkPos = ChunkPos(IVec2::new(-32, -8));
+const AREA_MAX: ChunkPos = ChunkPos(IVec2::new(31, 7));
+
+#[derive(Default)]
+struct Totals {
+    layers: usize,
+    single_block_layers: usize,
+    array_bytes: usize,
+    paletted_bytes: usize,
+}
+
+impl Totals {
+    fn add(&mut self, blocks: &[usize]) {
+        let paletted = PalettedBlocks::from_slice(blocks);
+        assert_eq!(paletted.to_vec(), blocks);
+        self.layers += 1;
+        if paletted.is_single_block() {
+            self.single_block_layers += 1;
+        }
+        self.array_bytes += size_of_val(blocks);
+        self.paletted_bytes += paletted.memory_usage();
+    }
+}
+
+fn main() {
+    let block_registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+    let world_generator = WorldGenerator::new(SEED, &block_registry, WorldConfig::default());
+
+    let start = Instant::now();
+    let mut totals = Totals::default();
+    for y in AREA_MIN.0.y..=AREA_MAX.0.y {
+        for x in AREA_MIN.0.x..=AREA_MAX.0.x {
+            let (foreground_blocks, background_blocks) = world_generator.generate_chunk(ChunkPos::new(x, y));
+            totals.add(&foreground_blocks);
+            totals.add(&background_blocks);
+        }
+    }
+
+    println!("{} chunk layers, {} of them a single block", totals.layers, totals.single_block_layers);
+    println!("arrays:  {:>9} bytes, {:>6} per layer", totals.array_bytes, totals.array_bytes / totals.layers);
+    println!("palette: {:>9} bytes, {:>6} per layer", totals.paletted_bytes, totals.paletted_bytes / totals.layers);
+    println!("the palette takes {:.1}% of the memory of the arrays", totals.paletted_bytes as f64 / totals.array_bytes as f64 * 100.0);
+    println!("took {:?}", start.elapsed());
+}
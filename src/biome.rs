@@ -0,0 +1,114 @@
+// The kind of land a column of the world has. Biomes only depend on X, the world
+// generator picks them from two noises, the temperature and the humidity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Biome {
+    PLAINS,
+    DESERT,
+    SNOW,
+    FOREST,
+    OCEAN,
+}
+
+pub const BIOMES: [Biome; 5] = [Biome::PLAINS, Biome::DESERT, Biome::SNOW, Biome::FOREST, Biome::OCEAN];
+
+// What grows on top of the surface of a biome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoration {
+    NONE,
+    // Tall grass and flowers
+    PLANTS,
+    TREES,
+    CACTI,
+}
+
+// The blocks are names in the block registry, the world generator looks up their IDs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiomeProperties {
+    pub surface_block: &'static str,
+    // The blocks under the surface, down to where the stone starts
+    pub subsurface_block: &'static str,
+    // The height of the terrain is base_height plus the terrain noise times height_amplitude,
+    // both are blended with the biomes around, so there are no cliffs at the borders
+    pub base_height: f64,
+    pub height_amplitude: f64,
+    pub decoration: Decoration,
+    // The chance that a column gets a decoration, from 0 to 1
+    pub decoration_chance: f64,
+}
+
+impl Biome {
+    // Both noises go from about -1 to 1.
+    pub fn from_climate(temperature: f64, humidity: f64) -> Biome {
+        if humidity > 0.3 {
+            return Biome::OCEAN;
+        }
+        if temperature < -0.2 {
+            return Biome::SNOW;
+        }
+        if temperature > 0.2 && humidity < 0.0 {
+            return Biome::DESERT;
+        }
+        if humidity > 0.05 {
+            return Biome::FOREST;
+        }
+        return Biome::PLAINS;
+    }
+
+    pub fn properties(&self) -> BiomeProperties {
+        return match self {
+            // The same terrain there was before biomes
+            Biome::PLAINS => BiomeProperties {
+                surface_block: "grass",
+                subsurface_block: "dirt",
+                base_height: 8.0,
+                height_amplitude: 16.0,
+                decoration: Decoration::PLANTS,
+                decoration_chance: 0.3,
+            },
+            Biome::DESERT => BiomeProperties {
+                surface_block: "sand",
+                subsurface_block: "sandstone",
+                base_height: 6.0,
+                height_amplitude: 8.0,
+                decoration: Decoration::CACTI,
+                decoration_chance: 0.08,
+            },
+            Biome::SNOW => BiomeProperties {
+                surface_block: "snow",
+                subsurface_block: "dirt",
+                base_height: 12.0,
+                height_amplitude: 24.0,
+                decoration: Decoration::TREES,
+                decoration_chance: 0.05,
+            },
+            Biome::FOREST => BiomeProperties {
+                surface_block: "grass",
+                subsurface_block: "dirt",
+                base_height: 10.0,
+                height_amplitude: 18.0,
+                decoration: Decoration::TREES,
+                decoration_chance: 0.3,
+            },
+            Biome::OCEAN => BiomeProperties {
+                surface_block: "sand",
+                subsurface_block: "dirt",
+                base_height: -14.0,
+                height_amplitude: 8.0,
+                decoration: Decoration::NONE,
+                decoration_chance: 0.0,
+            },
+        };
+    }
+}
+
+impl std::fmt::Display for Biome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Biome::PLAINS => write!(f, "Plains"),
+            Biome::DESERT => write!(f, "Desert"),
+            Biome::SNOW => write!(f, "Snow"),
+            Biome::FOREST => write!(f, "Forest"),
+            Biome::OCEAN => write!(f, "Ocean"),
+        }
+    }
+}
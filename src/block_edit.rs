@@ -0,0 +1,230 @@
+use std::collections::{HashSet, VecDeque};
+
+use macroquad::prelude::*;
+
+use crate::{
+    chunk::ChunkLayer,
+    chunk_manager::ChunkManager,
+    coordinates::{BlockPos, ChunkPos},
+};
+
+// The oldest actions are forgotten once the history has more edits than this
+pub const MAX_HISTORY_EDITS: usize = 65536;
+
+// A block that was changed, with what it was before, so it can be undone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockEdit {
+    pub position: BlockPos,
+    pub layer: ChunkLayer,
+    pub old_block: usize,
+    pub new_block: usize,
+}
+
+// Changes many blocks at once, like a fill or an explosion. Every block gets its light
+// updated right away, but the chunks are only meshed again once the batch is finished,
+// so a chunk with hundreds of changed blocks is still meshed only once.
+pub struct BlockEditBatch<'a> {
+    chunk_manager: &'a mut ChunkManager,
+    dirty_chunks: HashSet<ChunkPos>,
+    edits: Vec<BlockEdit>,
+}
+
+impl BlockEditBatch<'_> {
+    pub fn new(chunk_manager: &mut ChunkManager) -> BlockEditBatch<'_> {
+        return BlockEditBatch {
+            chunk_manager,
+            dirty_chunks: HashSet::new(),
+            edits: vec![],
+        };
+    }
+
+    // Returns true if the block was different, blocks in chunks that aren't loaded can't be changed.
+    pub fn set_block(&mut self, block_position: BlockPos, layer: ChunkLayer, block_type: usize) -> bool {
+        let Some(old_block) = self.chunk_manager.set_block_without_remesh(block_position, layer, block_type, &mut self.dirty_chunks) else {
+            return false;
+        };
+        self.edits.push(BlockEdit {
+            position: block_position,
+            layer,
+            old_block,
+            new_block: block_type,
+        });
+        return true;
+    }
+
+    // Meshes the changed chunks again, and returns how many blocks were actually changed.
+    // Dropping the batch meshes the chunks too.
+    pub fn finish(mut self) -> usize {
+        self.remesh();
+        return self.edits.len();
+    }
+
+    // Same as finish, but the changes can be undone later as a single action.
+    pub fn finish_recorded(mut self, history: &mut EditHistory) -> usize {
+        self.remesh();
+        let changed_blocks = self.edits.len();
+        history.push(std::mem::take(&mut self.edits));
+        return changed_blocks;
+    }
+
+    fn remesh(&mut self) {
+        let dirty_chunks = std::mem::take(&mut self.dirty_chunks);
+        self.chunk_manager.remesh_chunks(dirty_chunks);
+    }
+}
+
+impl Drop for BlockEditBatch<'_> {
+    fn drop(&mut self) {
+        self.remesh();
+    }
+}
+
+// The actions done by the player that can be undone and redone, where an
+// action is every edit made by a single click, like a whole rectangle.
+pub struct EditHistory {
+    undo_actions: VecDeque<Vec<BlockEdit>>,
+    redo_actions: Vec<Vec<BlockEdit>>,
+    // The amount of edits in both lists
+    edit_count: usize,
+    max_edits: usize,
+}
+
+impl EditHistory {
+    pub fn new(max_edits: usize) -> EditHistory {
+        return EditHistory {
+            undo_actions: VecDeque::new(),
+            redo_actions: vec![],
+            edit_count: 0,
+            max_edits,
+        };
+    }
+
+    // Doing something new makes the undone actions impossible to redo.
+    pub fn push(&mut self, action: Vec<BlockEdit>) {
+        if action.is_empty() {
+            return;
+        }
+        for redo_action in self.redo_actions.drain(..) {
+            self.edit_count -= redo_action.len();
+        }
+        // An action that doesn't fit at all can't be undone, and the ones
+        // before it can't be undone either, since they would be undone out of order.
+        if action.len() > self.max_edits {
+            self.undo_actions.clear();
+            self.edit_count = 0;
+            return;
+        }
+
+        self.edit_count += action.len();
+        self.undo_actions.push_back(action);
+        while self.edit_count > self.max_edits {
+            let oldest = self.undo_actions.pop_front().unwrap();
+            self.edit_count -= oldest.len();
+        }
+    }
+
+    // The next action to undo, which then becomes the next one to redo.
+    pub fn take_undo(&mut self) -> Option<Vec<BlockEdit>> {
+        let action = self.undo_actions.pop_back()?;
+        self.redo_actions.push(action.clone());
+        return Some(action);
+    }
+
+    // The next action to redo, which then becomes the next one to undo.
+    pub fn take_redo(&mut self) -> Option<Vec<BlockEdit>> {
+        let action = self.redo_actions.pop()?;
+        self.undo_actions.push_back(action.clone());
+        return Some(action);
+    }
+
+    // Chunks that were unloaded since the action are loaded again to undo it.
+    pub fn undo(&mut self, chunk_manager: &mut ChunkManager) {
+        let Some(action) = self.take_undo() else {
+            return;
+        };
+        apply_edits(chunk_manager, action.iter().rev().map(|edit| (edit.position, edit.layer, edit.old_block)));
+    }
+
+    pub fn redo(&mut self, chunk_manager: &mut ChunkManager) {
+        let Some(action) = self.take_redo() else {
+            return;
+        };
+        apply_edits(chunk_manager, action.iter().map(|edit| (edit.position, edit.layer, edit.new_block)));
+    }
+
+    pub fn input(&mut self, chunk_manager: &mut ChunkManager) {
+        if !is_key_down(KeyCode::LeftControl) {
+            return;
+        }
+        if is_key_pressed(KeyCode::Z) {
+            self.undo(chunk_manager);
+        }
+        if is_key_pressed(KeyCode::Y) {
+            self.redo(chunk_manager);
+        }
+    }
+}
+
+fn apply_edits(chunk_manager: &mut ChunkManager, edits: impl Iterator<Item = (BlockPos, ChunkLayer, usize)>) {
+    let edits: Vec<(BlockPos, ChunkLayer, usize)> = edits.collect();
+    for (position, _, _) in edits.iter() {
+        chunk_manager.load_chunk_now(position.chunk(chunk_manager.config().chunk_width()));
+    }
+
+    let mut batch = chunk_manager.edit_batch();
+    for (position, layer, block) in edits {
+        batch.set_block(position, layer, block);
+    }
+    batch.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(length: usize, new_block: usize) -> Vec<BlockEdit> {
+        return (0..length)
+            .map(|x| BlockEdit {
+                position: BlockPos::new(x as i32, 0),
+                layer: ChunkLayer::FOREGROUND,
+                old_block: 0,
+                new_block,
+            })
+            .collect();
+    }
+
+    #[test]
+    fn undo_and_redo_go_back_and_forth() {
+        let mut history = EditHistory::new(100);
+        history.push(action(2, 1));
+        history.push(action(3, 2));
+        history.push(vec![]);
+
+        assert_eq!(history.take_undo(), Some(action(3, 2)));
+        assert_eq!(history.take_undo(), Some(action(2, 1)));
+        assert_eq!(history.take_undo(), None);
+        assert_eq!(history.take_redo(), Some(action(2, 1)));
+
+        // Doing something new forgets what was undone
+        history.push(action(1, 3));
+        assert_eq!(history.take_redo(), None);
+        assert_eq!(history.take_undo(), Some(action(1, 3)));
+        assert_eq!(history.take_undo(), Some(action(2, 1)));
+    }
+
+    #[test]
+    fn history_forgets_the_oldest_actions() {
+        let mut history = EditHistory::new(10);
+        history.push(action(4, 1));
+        history.push(action(4, 2));
+        history.push(action(4, 3));
+        assert_eq!(history.edit_count, 8);
+        assert_eq!(history.take_undo(), Some(action(4, 3)));
+        assert_eq!(history.take_undo(), Some(action(4, 2)));
+        assert_eq!(history.take_undo(), None);
+
+        history.push(action(11, 4));
+        assert_eq!(history.edit_count, 0);
+        assert_eq!(history.take_undo(), None);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::Deserialize;
+
+pub const AIR: usize = 0;
+
+// What a block looks like and how it behaves, see assets/blocks.ron
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BlockProperties {
+    pub name: String,
+    #[serde(default)]
+    pub atlas_index: Option<usize>,
+    #[serde(default = "default_true")]
+    pub solid: bool,
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default = "default_true")]
+    pub collidable: bool,
+    #[serde(default = "default_hardness")]
+    pub hardness: f32,
+    #[serde(default)]
+    pub light_emission: u8,
+    #[serde(default)]
+    pub fluid: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_hardness() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+struct BlockRegistryFile {
+    atlas_columns: usize,
+    blocks: Vec<BlockProperties>,
+}
+
+// Every block ID is an index into this registry, where the ID 0 is always air.
+pub struct BlockRegistry {
+    atlas_columns: usize,
+    blocks: Vec<BlockProperties>,
+    ids_by_name: HashMap<String, usize>,
+}
+
+impl BlockRegistry {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<BlockRegistry> {
+        return BlockRegistry::from_ron_str(&fs::read_to_string(path)?);
+    }
+
+    pub fn from_ron_str(contents: &str) -> io::Result<BlockRegistry> {
+        let file: BlockRegistryFile = ron::from_str(contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+        let air = BlockProperties {
+            name: "air".to_string(),
+            atlas_index: None,
+            solid: false,
+            transparent: true,
+            collidable: false,
+            hardness: 0.0,
+            light_emission: 0,
+            fluid: false,
+        };
+
+        let mut blocks = vec![air];
+        blocks.extend(file.blocks);
+
+        // Chunks are saved with 16 bits per block
+        if blocks.len() > u16::MAX as usize + 1 {
+            return Err(invalid_data(format!("there are {} blocks, the maximum is {}", blocks.len(), u16::MAX as usize + 1)));
+        }
+
+        let mut ids_by_name: HashMap<String, usize> = HashMap::new();
+        for (id, block) in blocks.iter().enumerate() {
+            if let Some(atlas_index) = block.atlas_index {
+                if atlas_index >= file.atlas_columns {
+                    return Err(invalid_data(format!(
+                        "block \"{}\" uses atlas index {}, but the atlas only has {} columns",
+                        block.name, atlas_index, file.atlas_columns
+                    )));
+                }
+            }
+            if block.light_emission > 15 {
+                return Err(invalid_data(format!("block \"{}\" emits more light than 15", block.name)));
+            }
+            if ids_by_name.insert(block.name.clone(), id).is_some() {
+                return Err(invalid_data(format!("block \"{}\" is registered twice", block.name)));
+            }
+        }
+
+        return Ok(BlockRegistry {
+            atlas_columns: file.atlas_columns,
+            blocks,
+            ids_by_name,
+        });
+    }
+
+    // IDs that don't exist are treated as air.
+    pub fn get(&self, id: usize) -> &BlockProperties {
+        return self.blocks.get(id).unwrap_or(&self.blocks[AIR]);
+    }
+
+    pub fn id_by_name(&self, name: &str) -> Option<usize> {
+        return self.ids_by_name.get(name).copied();
+    }
+
+    // The amount of blocks, including air.
+    pub fn len(&self) -> usize {
+        return self.blocks.len();
+    }
+
+    // Air is always registered, so this is never true.
+    pub fn is_empty(&self) -> bool {
+        return self.blocks.is_empty();
+    }
+
+    pub fn atlas_columns(&self) -> usize {
+        return self.atlas_columns;
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    return io::Error::new(ErrorKind::InvalidData, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_the_bundled_registry() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        assert_eq!(registry.get(AIR).name, "air");
+        assert!(!registry.get(AIR).collidable);
+        assert_eq!(registry.id_by_name("grass"), Some(1));
+        assert_eq!(registry.id_by_name("dirt"), Some(2));
+        assert_eq!(registry.id_by_name("stone"), Some(3));
+        assert!(registry.get(3).solid);
+        assert_eq!(registry.get(registry.len()).name, "air");
+    }
+
+    #[test]
+    fn rejects_invalid_registries() {
+        let out_of_atlas = "(atlas_columns: 1, blocks: [(name: \"a\", atlas_index: Some(1))])";
+        assert!(BlockRegistry::from_ron_str(out_of_atlas).is_err());
+
+        let duplicated = "(atlas_columns: 1, blocks: [(name: \"a\"), (name: \"a\")])";
+        assert!(BlockRegistry::from_ron_str(duplicated).is_err());
+
+        let air = "(atlas_columns: 1, blocks: [(name: \"air\")])";
+        assert!(BlockRegistry::from_ron_str(air).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+// The blocks of one layer of a chunk. Instead of a whole usize for every block, every
+// different block in the layer is put in a palette, and the blocks only store their
+// index in the palette, with as few bits as the size of the palette needs.
+// A layer that is a single block, like the sky or deep underground, doesn't store any
+// indices at all. Only widths that divide 64 are used, so an index never spans two words.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PalettedBlocks {
+    // How many blocks there are, the area of the chunk
+    len: usize,
+    palette: Vec<usize>,
+    // 0 when the palette has a single block, then data is empty
+    bits_per_block: u32,
+    data: Box<[u64]>,
+}
+
+const BIT_WIDTHS: [u32; 5] = [1, 2, 4, 8, 16];
+
+impl PalettedBlocks {
+    // A layer filled with a single block.
+    pub fn filled(block: usize, len: usize) -> PalettedBlocks {
+        return PalettedBlocks {
+            len,
+            palette: vec![block],
+            bits_per_block: 0,
+            data: Box::new([]),
+        };
+    }
+
+    pub fn from_slice(blocks: &[usize]) -> PalettedBlocks {
+        let mut palette: Vec<usize> = vec![];
+        for block in blocks.iter() {
+            if !palette.contains(block) {
+                palette.push(*block);
+            }
+        }
+        if palette.len() == 1 {
+            return PalettedBlocks::filled(palette[0], blocks.len());
+        }
+
+        let mut storage = PalettedBlocks {
+            len: blocks.len(),
+            bits_per_block: bits_for_palette(palette.len()),
+            palette,
+            data: Box::new([]),
+        };
+        storage.data = vec![0; words_for_bits(storage.bits_per_block, storage.len)].into_boxed_slice();
+        for (index, block) in blocks.iter().enumerate() {
+            let palette_index = storage.palette.iter().position(|b| b == block).unwrap();
+            storage.write_index(index, palette_index);
+        }
+        return storage;
+    }
+
+    pub fn to_vec(&self) -> Vec<usize> {
+        if self.bits_per_block == 0 {
+            return vec![self.palette[0]; self.len];
+        }
+        let mut blocks = vec![0; self.len];
+        for (index, block) in blocks.iter_mut().enumerate() {
+            *block = self.palette[self.read_index(index)];
+        }
+        return blocks;
+    }
+
+    pub fn get(&self, index: usize) -> usize {
+        if self.bits_per_block == 0 {
+            return self.palette[0];
+        }
+        return self.palette[self.read_index(index)];
+    }
+
+    // Returns the block that was there before.
+    pub fn set(&mut self, index: usize, block: usize) -> usize {
+        let old_block = self.get(index);
+        if old_block == block {
+            return old_block;
+        }
+
+        let palette_index = match self.palette.iter().position(|b| *b == block) {
+            Some(palette_index) => palette_index,
+            None => {
+                // Blocks that were replaced stay in the palette, they are only
+                // dropped when the palette is full and has to be rebuilt anyway
+                if self.bits_per_block == 0 || self.palette.len() == 1 << self.bits_per_block {
+                    let mut blocks = self.to_vec();
+                    blocks[index] = block;
+                    *self = PalettedBlocks::from_slice(&blocks);
+                    return old_block;
+                }
+                self.palette.push(block);
+                self.palette.len() - 1
+            }
+        };
+        self.write_index(index, palette_index);
+        return old_block;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    // Every block in the layer is the same one.
+    pub fn is_single_block(&self) -> bool {
+        return self.bits_per_block == 0;
+    }
+
+    pub fn bits_per_block(&self) -> u32 {
+        return self.bits_per_block;
+    }
+
+    // How many bytes this layer takes, including what's on the heap.
+    pub fn memory_usage(&self) -> usize {
+        return std::mem::size_of::<PalettedBlocks>()
+            + self.palette.capacity() * std::mem::size_of::<usize>()
+            + self.data.len() * std::mem::size_of::<u64>();
+    }
+
+    fn read_index(&self, index: usize) -> usize {
+        let (word, shift) = self.locate(index);
+        let mask = (1 << self.bits_per_block) - 1;
+        return ((self.data[word] >> shift) & mask) as usize;
+    }
+
+    fn write_index(&mut self, index: usize, palette_index: usize) {
+        let (word, shift) = self.locate(index);
+        let mask: u64 = (1 << self.bits_per_block) - 1;
+        self.data[word] = (self.data[word] & !(mask << shift)) | ((palette_index as u64) << shift);
+    }
+
+    fn locate(&self, index: usize) -> (usize, u32) {
+        let per_word = 64 / self.bits_per_block as usize;
+        return (index / per_word, (index % per_word) as u32 * self.bits_per_block);
+    }
+}
+
+fn bits_for_palette(palette_length: usize) -> u32 {
+    return *BIT_WIDTHS
+        .iter()
+        .find(|bits| palette_length <= 1 << **bits)
+        .expect("a chunk can't have more different blocks than it has tiles");
+}
+
+// The chunk width can be anything, so the last word isn't always full.
+fn words_for_bits(bits_per_block: u32, len: usize) -> usize {
+    return len.div_ceil(64 / bits_per_block as usize);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The areas of chunks 16, 5 and 128 blocks wide
+    const AREAS: [usize; 3] = [256, 25, 16384];
+
+    fn pattern(different_blocks: usize, len: usize) -> Vec<usize> {
+        return (0..len).map(|i| (i * 7) % different_blocks + 100).collect();
+    }
+
+    #[test]
+    fn blocks_survive_the_round_trip() {
+        for len in AREAS {
+            for different_blocks in [1, 2, 3, 5, 16, 17, 200, len] {
+                let blocks = pattern(different_blocks, len);
+                let storage = PalettedBlocks::from_slice(&blocks);
+                assert_eq!(storage.to_vec(), blocks);
+                assert!((0..len).all(|i| storage.get(i) == blocks[i]));
+            }
+        }
+        assert_eq!(PalettedBlocks::from_slice(&pattern(3, 256)).bits_per_block(), 2);
+        assert_eq!(PalettedBlocks::from_slice(&pattern(17, 256)).bits_per_block(), 8);
+    }
+
+    #[test]
+    fn single_block_layers_dont_store_indices() {
+        let mut storage = PalettedBlocks::from_slice(&[3; 256]);
+        assert!(storage.is_single_block());
+        assert_eq!(storage, PalettedBlocks::filled(3, 256));
+        assert_eq!(storage.get(200), 3);
+
+        assert_eq!(storage.set(10, 4), 3);
+        assert!(!storage.is_single_block());
+        assert_eq!(storage.get(10), 4);
+        assert_eq!(storage.get(11), 3);
+        assert_eq!(storage.to_vec().len(), 256);
+    }
+
+    #[test]
+    fn setting_blocks_matches_an_array() {
+        for len in AREAS {
+            let mut blocks = vec![0; len];
+            let mut storage = PalettedBlocks::filled(0, len);
+            // Every block gets replaced a few times, so the palette fills up with unused blocks
+            for step in 0..len * 4 {
+                let index = (step * 31) % len;
+                let block = (step / 50) % 40;
+                assert_eq!(storage.set(index, block), blocks[index]);
+                blocks[index] = block;
+            }
+            assert_eq!(storage.to_vec(), blocks);
+            if len == 256 {
+                // Only the last few blocks are left, the palette only grows as much as it needs to
+                assert!(storage.bits_per_block() <= 4);
+            }
+        }
+    }
+}
@@ -1,17 +1,19 @@
-use macroquad::models::Vertex;
 use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::collision::bounding_box::AxisAlignedRectangle;
+use crate::{
+    block_storage::PalettedBlocks,
+    coordinates::{ChunkPos, LocalPos},
+    lighting::ChunkLight,
+};
 
-pub const TILE_SIZE: usize = 32;
-pub const CHUNK_WIDTH: usize = 16;
-pub const CHUNK_AREA: usize = CHUNK_WIDTH * CHUNK_WIDTH;
-pub const BLOCK_COUNT: usize = 8;
-
-const CHUNK_PIXEL_SIZE: f32 = CHUNK_WIDTH as f32 * TILE_SIZE as f32;
+// Has to be bumped every time the meaning of the block IDs changes,
+// like when blocks are removed or reordered in assets/blocks.ron.
+// See the migrations in region.rs
+pub const BLOCK_LAYOUT_VERSION: u32 = 1;
 
 #[allow(dead_code)]
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ChunkLayer {
     FOREGROUND,
     BACKGROUND
@@ -35,165 +37,55 @@ impl ChunkLayer {
     }
 }
 
+// The blocks and light of a part of the world, without anything for drawing it,
+// the meshes are built by the chunk manager and drawn by the ChunkRenderer.
+// The blocks are paletted (see block_storage.rs), so they can only be reached through get_block and set_block.
 pub struct Chunk {
-    pub position: IVec2,
-    pub foreground_blocks: [usize; CHUNK_AREA],
-    pub background_blocks: [usize; CHUNK_AREA],
-    pub foreground_mesh: Mesh,
-    pub background_mesh: Mesh,
-    pub aabb: AxisAlignedRectangle,
+    pub position: ChunkPos,
+    // The chunk width of the world, the blocks are chunk_width * chunk_width long
+    chunk_width: usize,
+    foreground_blocks: PalettedBlocks,
+    background_blocks: PalettedBlocks,
+    pub light: ChunkLight,
+    // Set when the blocks of this chunk were changed after it was
+    // generated or loaded, so it has to be saved when unloaded.
+    pub modified: bool,
+    // Changes every time the chunk gets meshed, see RemeshJob
+    pub mesh_revision: u64,
 }
 
 impl Chunk {
-    pub fn new(position: IVec2, foreground_blocks: [usize; CHUNK_AREA], background_blocks: [usize; CHUNK_AREA], texture_atlas: Texture2D) -> Chunk {
-        const CHUNK_WORLD_WIDTH: f32 = CHUNK_WIDTH as f32 * TILE_SIZE as f32;
-        let mut indices = [0; CHUNK_AREA * 6];
-        let mut offset: usize = 0;
-        for i in (0..(256 * 6)).step_by(6) {
-            indices[i + 0] = 0 + offset as u16;
-            indices[i + 1] = 1 + offset as u16;
-            indices[i + 2] = 2 + offset as u16;
-
-            indices[i + 3] = 2 + offset as u16;
-            indices[i + 4] = 3 + offset as u16;
-            indices[i + 5] = 0 + offset as u16;
-
-            offset += 4;
-        }
-
-        let bottom_left_worldpos = position.as_vec2() * CHUNK_WORLD_WIDTH;
-        let size = Vec2::splat(CHUNK_WORLD_WIDTH);
-        let chunk_aabb = AxisAlignedRectangle {
-            center_pos: bottom_left_worldpos + size * 0.5,
-            size,
-        };
-
-        let mut new_chunk = Chunk {
-            foreground_blocks,
-            background_blocks,
+    pub fn new(position: ChunkPos, chunk_width: usize, foreground_blocks: &[usize], background_blocks: &[usize], light: ChunkLight) -> Chunk {
+        assert_eq!(foreground_blocks.len(), chunk_width * chunk_width);
+        assert_eq!(background_blocks.len(), chunk_width * chunk_width);
+        return Chunk {
             position,
-            aabb: chunk_aabb,
-            foreground_mesh: Mesh {
-                indices: indices.to_vec(),
-                vertices: vec![],
-                texture: Some(texture_atlas.clone()),
-            },
-            background_mesh: Mesh {
-                indices: indices.to_vec(),
-                vertices: vec![],
-                texture: Some(texture_atlas),
-            },
+            chunk_width,
+            foreground_blocks: PalettedBlocks::from_slice(foreground_blocks),
+            background_blocks: PalettedBlocks::from_slice(background_blocks),
+            light,
+            modified: false,
+            mesh_revision: 0,
         };
-        new_chunk.remesh();
-        return new_chunk;
     }
 
-    // This function is causing some weird high memory usage
-    // that is still unknown.
-    pub fn remesh(&mut self) {
-        let mut foreground_vertices = [Vertex {
-            position: Vec3::ZERO,
-            uv: Vec2::ZERO,
-            color: WHITE,
-        }; CHUNK_AREA * 4];
-        let mut background_vertices = [Vertex {
-            position: Vec3::ZERO,
-            uv: Vec2::ZERO,
-            color: GRAY,
-        }; CHUNK_AREA * 4];
-
-        for y in 0..16 {
-            for x in 0..16 {
-                let index: usize = x + (y * 16);
-                let vert_index = index * 4;
-                if self.foreground_blocks[index] > 0 || self.background_blocks[index] > 0 {
-                    let pos_template = |pos: usize, x: bool| {
-                        pos as f32 * TILE_SIZE as f32 + (x as usize * TILE_SIZE) as f32
-                    };
-                    let p = |a: bool, b: bool| {
-                        Vec3::new(
-                            ((self.position.x * CHUNK_WIDTH as i32) * TILE_SIZE as i32) as f32
-                                + pos_template(x, a),
-                            ((self.position.y * CHUNK_WIDTH as i32) * TILE_SIZE as i32) as f32
-                                + pos_template(y, b),
-                            0.0,
-                        )
-                    };
-
-                    let block_uv_unit = 1.0 / BLOCK_COUNT as f32;
-                    let set_vertex_values = |blocks: &[usize; CHUNK_AREA], vertices: &mut [Vertex; CHUNK_AREA * 4]| {
-                        // It needs to not consider the block ID number 0 because it's just air
-                        let block_uv_index = block_uv_unit * (blocks[index] - 1) as f32;
-    
-                        vertices[vert_index].position = p(false, false);
-                        vertices[vert_index].uv = Vec2::new(block_uv_index, 1.0);
-    
-                        vertices[vert_index + 1].position = p(true, false);
-                        vertices[vert_index + 1].uv = Vec2::new(block_uv_index + block_uv_unit, 1.0);
-    
-                        vertices[vert_index + 2].position = p(true, true);
-                        vertices[vert_index + 2].uv = Vec2::new(block_uv_index + block_uv_unit, 0.0);
-    
-                        vertices[vert_index + 3].position = p(false, true);
-                        vertices[vert_index + 3].uv = Vec2::new(block_uv_index, 0.0);
-                    };
-
-                    if self.foreground_blocks[index] > 0 {
-                        set_vertex_values(&self.foreground_blocks, &mut foreground_vertices);
-                    }
-                    if self.background_blocks[index] > 0 {
-                        set_vertex_values(&self.background_blocks, &mut background_vertices);
-                    }
-                }
-            }
-        }
-
-        self.foreground_mesh.vertices = foreground_vertices.to_vec();
-        self.background_mesh.vertices = background_vertices.to_vec();
+    pub fn get_block(&self, local: LocalPos, layer: ChunkLayer) -> usize {
+        return self.blocks(layer).get(local.index(self.chunk_width));
     }
 
-    pub fn draw(&self, debug: bool) {
-        draw_mesh(&self.background_mesh);
-        draw_mesh(&self.foreground_mesh);
-
-        if debug {
-            for y in 0..CHUNK_WIDTH {
-                draw_line(
-                    self.position.x as f32 * CHUNK_PIXEL_SIZE,
-                    (self.position.y as f32 * CHUNK_PIXEL_SIZE) + (y as f32 * TILE_SIZE as f32),
-                    (self.position.x as f32 * CHUNK_PIXEL_SIZE) + CHUNK_PIXEL_SIZE,
-                    (self.position.y as f32 * CHUNK_PIXEL_SIZE) + (y as f32 * TILE_SIZE as f32),
-                    2.0,
-                    color_u8!(255.0, 255.0, 255.0, 128.0),
-                );
-            }
-            for x in 0..CHUNK_WIDTH {
-                draw_line(
-                    (self.position.x as f32 * CHUNK_PIXEL_SIZE) + (x as f32 * TILE_SIZE as f32),
-                    self.position.y as f32 * CHUNK_PIXEL_SIZE,
-                    (self.position.x as f32 * CHUNK_PIXEL_SIZE) + (x as f32 * TILE_SIZE as f32),
-                    (self.position.y as f32 * CHUNK_PIXEL_SIZE) + CHUNK_PIXEL_SIZE,
-                    2.0,
-                    color_u8!(255.0, 255.0, 255.0, 128.0),
-                );
-            }
+    // Returns the block that was there before. Doesn't set modified.
+    pub fn set_block(&mut self, local: LocalPos, layer: ChunkLayer, block: usize) -> usize {
+        let blocks = match layer {
+            ChunkLayer::FOREGROUND => &mut self.foreground_blocks,
+            ChunkLayer::BACKGROUND => &mut self.background_blocks
+        };
+        return blocks.set(local.index(self.chunk_width), block);
+    }
 
-            draw_line(
-                self.position.x as f32 * CHUNK_PIXEL_SIZE,
-                self.position.y as f32 * CHUNK_PIXEL_SIZE,
-                (self.position.x as f32 * CHUNK_PIXEL_SIZE) + CHUNK_PIXEL_SIZE,
-                self.position.y as f32 * CHUNK_PIXEL_SIZE,
-                5.0,
-                BLUE,
-            );
-            draw_line(
-                self.position.x as f32 * CHUNK_PIXEL_SIZE,
-                self.position.y as f32 * CHUNK_PIXEL_SIZE,
-                self.position.x as f32 * CHUNK_PIXEL_SIZE,
-                (self.position.y as f32 * CHUNK_PIXEL_SIZE) + CHUNK_PIXEL_SIZE,
-                5.0,
-                RED,
-            );
-        }
+    pub fn blocks(&self, layer: ChunkLayer) -> &PalettedBlocks {
+        return match layer {
+            ChunkLayer::FOREGROUND => &self.foreground_blocks,
+            ChunkLayer::BACKGROUND => &self.background_blocks
+        };
     }
 }
@@ -1,182 +1,565 @@
 use crate::{
-    chunk::{Chunk, ChunkLayer, CHUNK_AREA, CHUNK_WIDTH, TILE_SIZE},
+    block_edit::BlockEditBatch,
+    block_registry::BlockRegistry,
+    chunk::{Chunk, ChunkLayer},
+    chunk_mesh::{build_chunk_vertices, casts_ambient_occlusion, ChunkNeighbourhood, ChunkVertices, MeshingMode},
+    coordinates::{BlockPos, ChunkPos},
+    chunk_workers::{run_chunk_job, ChunkJob, ChunkWorkerPool, FinishedChunk, RemeshJob, RemeshedChunk, WorkerResult},
     collision::{bounding_box::AxisAlignedRectangle, RectangleCorners},
+    lighting::{assumed_sunlight, update_light_at, LoadedChunksLight},
+    world_config::WorldConfig,
+    world_generator::WorldGenerator,
+    world_save::WorldSave,
 };
 use macroquad::prelude::*;
-use noise::{HybridMulti, NoiseFn, Perlin};
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+// Vertex lists given back by the renderer are kept up to this many, to be filled by the next remeshes
+const MAX_SPARE_VERTICES: usize = 16;
+
+// Fixing the light around a new chunk still happens on the main thread,
+// so only this many finished chunks are added to the world every frame.
+const MAX_CHUNKS_INTEGRATED_PER_FRAME: usize = 8;
 
 pub struct ChunkManager {
-    chunks: HashMap<IVec2, Chunk>,
-    blocks_atlas_texture: Texture2D
+    // The same one the world generator has
+    config: WorldConfig,
+    chunks: HashMap<ChunkPos, Chunk>,
+    // Chunks that were sent to the workers and didn't come back yet, with the generation
+    // of the latest request. Results of older requests for the same chunk are thrown away.
+    pending_chunks: HashMap<ChunkPos, u64>,
+    next_load_generation: u64,
+    // Chunks that were meshed since the renderer last took them
+    mesh_updates: HashMap<ChunkPos, ChunkVertices>,
+    spare_vertices: Vec<ChunkVertices>,
+    // Never goes down, so a chunk that gets unloaded and loaded again can't match an old revision
+    next_mesh_revision: u64,
+    meshing_mode: MeshingMode,
+    world_save: WorldSave,
+    workers: ChunkWorkerPool,
+    loading_config: ChunkLoadingConfig,
+    block_registry: Arc<BlockRegistry>,
+    // Also used by the lighting, to know where the sky is in chunks that aren't loaded
+    world_generator: Arc<WorldGenerator>,
 }
 
 impl ChunkManager {
-    pub async fn new() -> ChunkManager {
-        let blocks_atlas_texture = load_texture("assets/textures/blocks.png").await.unwrap();
-        blocks_atlas_texture.set_filter(FilterMode::Nearest);
+    pub fn new(
+        world_save: WorldSave,
+        world_generator: WorldGenerator,
+        block_registry: Arc<BlockRegistry>,
+        loading_config: ChunkLoadingConfig,
+    ) -> ChunkManager {
+        let world_generator = Arc::new(world_generator);
 
         ChunkManager {
-            chunks: HashMap::<IVec2, Chunk>::new(),
-            blocks_atlas_texture,
+            config: *world_generator.config(),
+            chunks: HashMap::<ChunkPos, Chunk>::new(),
+            pending_chunks: HashMap::new(),
+            next_load_generation: 1,
+            mesh_updates: HashMap::new(),
+            spare_vertices: vec![],
+            next_mesh_revision: 1,
+            meshing_mode: MeshingMode::SIMPLE,
+            world_save,
+            workers: ChunkWorkerPool::new(world_generator.clone(), block_registry.clone()),
+            loading_config: loading_config.validated(),
+            block_registry,
+            world_generator,
         }
     }
 
-    pub fn draw(&self, screen_aabb: &AxisAlignedRectangle, debug: bool) {
-        // Only render chunks that are inside the screen!!!
-        // ================================================
-        self.chunks
-            .values()
-            .filter(|chunk| screen_aabb.intersects(&chunk.aabb))
-            .for_each(|chunk| chunk.draw(debug));
+    // To change many blocks at once, use edit_batch instead, so every chunk is only meshed once.
+    pub fn set_block(&mut self, block_position: BlockPos, layer: ChunkLayer, block_type: usize) {
+        let mut batch = self.edit_batch();
+        batch.set_block(block_position, layer, block_type);
+        batch.finish();
     }
 
-    pub fn set_block(&mut self, block_position: IVec2, layer: ChunkLayer, block_type: usize) {
-        let chunk_position = get_chunk_position(block_position);
-        let Some(chunk) = self.chunks.get_mut(&chunk_position) else {
-            return;
+    pub fn edit_batch(&mut self) -> BlockEditBatch<'_> {
+        return BlockEditBatch::new(self);
+    }
+
+    // Changes the block and its light without meshing anything, the chunks that have to be
+    // meshed again are added to dirty_chunks. Returns the old block if the block changed.
+    pub fn set_block_without_remesh(&mut self, block_position: BlockPos, layer: ChunkLayer, block_type: usize, dirty_chunks: &mut HashSet<ChunkPos>) -> Option<usize> {
+        let (chunk_position, local) = block_position.split(self.config.chunk_width());
+        let chunk = self.chunks.get_mut(&chunk_position)?;
+        let old_block = chunk.set_block(local, layer, block_type);
+        if old_block == block_type {
+            return None;
+        }
+        chunk.modified = true;
+
+        dirty_chunks.insert(chunk_position);
+        // Only the foreground blocks light can go through, or be emitted by
+        if layer == ChunkLayer::FOREGROUND {
+            update_light_at(&mut self.light_world(dirty_chunks), block_position.to_ivec2());
+            // The corners of the tiles around it are shaded by this block too, and they can be in other chunks
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    dirty_chunks.insert((block_position + ivec2(x, y)).chunk(self.config.chunk_width()));
+                }
+            }
+        }
+        return Some(old_block);
+    }
+
+    fn light_world<'a>(&'a mut self, dirty_chunks: &'a mut HashSet<ChunkPos>) -> LoadedChunksLight<'a> {
+        return LoadedChunksLight {
+            chunks: &mut self.chunks,
+            block_registry: &self.block_registry,
+            world_generator: &self.world_generator,
+            dirty_chunks,
         };
-        let relative_coords = get_relative_position(block_position, chunk_position);
-        match layer {
-            ChunkLayer::FOREGROUND => chunk.foreground_blocks[get_index_from_position(relative_coords)] = block_type,
-            ChunkLayer::BACKGROUND => chunk.background_blocks[get_index_from_position(relative_coords)] = block_type
+    }
+
+    // Every loaded chunk is meshed again with the new mode.
+    pub fn set_meshing_mode(&mut self, meshing_mode: MeshingMode) {
+        if self.meshing_mode == meshing_mode {
+            return;
         }
-        
-        chunk.remesh();
+        self.meshing_mode = meshing_mode;
+        self.remesh_chunks(self.chunks.keys().copied().collect());
     }
 
-    pub fn get_block(&self, block_position: IVec2, layer: ChunkLayer) -> usize {
-        let chunk_position = get_chunk_position(block_position);
+    pub fn meshing_mode(&self) -> MeshingMode {
+        return self.meshing_mode;
+    }
+
+    // Chunks that aren't loaded are skipped.
+    pub fn remesh_chunks(&mut self, chunk_positions: HashSet<ChunkPos>) {
+        for chunk_position in chunk_positions {
+            if !self.chunks.contains_key(&chunk_position) {
+                continue;
+            }
+            let neighbourhood = self.get_neighbourhood(chunk_position);
+            self.next_mesh_revision += 1;
+            let chunk = self.chunks.get_mut(&chunk_position).unwrap();
+            // Remeshes that are still running on the workers are older than this one
+            chunk.mesh_revision = self.next_mesh_revision;
+            let foreground_blocks = chunk.blocks(ChunkLayer::FOREGROUND).to_vec();
+            let background_blocks = chunk.blocks(ChunkLayer::BACKGROUND).to_vec();
+            let mut vertices = self.mesh_updates.remove(&chunk_position).or_else(|| self.spare_vertices.pop()).unwrap_or_default();
+            build_chunk_vertices(&foreground_blocks, &background_blocks, &neighbourhood, &self.block_registry, &self.config, self.meshing_mode, &mut vertices);
+            self.mesh_updates.insert(chunk_position, vertices);
+        }
+    }
+
+    // Same as remesh_chunks, but the vertices are built by the workers, the
+    // chunks keep their old meshes until the new ones arrive.
+    fn remesh_chunks_on_workers(&mut self, chunk_positions: HashSet<ChunkPos>) {
+        for chunk_position in chunk_positions {
+            if !self.chunks.contains_key(&chunk_position) {
+                continue;
+            }
+            let neighbourhood = self.get_neighbourhood(chunk_position);
+            self.next_mesh_revision += 1;
+            let chunk = self.chunks.get_mut(&chunk_position).unwrap();
+            chunk.mesh_revision = self.next_mesh_revision;
+            let job = RemeshJob {
+                position: chunk_position,
+                revision: chunk.mesh_revision,
+                foreground_blocks: chunk.blocks(ChunkLayer::FOREGROUND).to_vec(),
+                background_blocks: chunk.blocks(ChunkLayer::BACKGROUND).to_vec(),
+                neighbourhood,
+                vertices: self.spare_vertices.pop().unwrap_or_default(),
+            };
+            self.workers.submit_remesh(job, self.meshing_mode);
+        }
+    }
+
+    fn apply_remesh(&mut self, remeshed: RemeshedChunk) {
+        let up_to_date = self
+            .chunks
+            .get(&remeshed.position)
+            .is_some_and(|chunk| chunk.mesh_revision == remeshed.revision);
+        if !up_to_date {
+            self.recycle_vertices(remeshed.vertices);
+            return;
+        }
+        if let Some(vertices) = self.mesh_updates.insert(remeshed.position, remeshed.vertices) {
+            self.recycle_vertices(vertices);
+        }
+    }
+
+    // Tiles in chunks that aren't loaded are assumed to be untouched terrain without any shadows.
+    fn get_neighbourhood(&self, chunk_position: ChunkPos) -> ChunkNeighbourhood {
+        let chunk_width = self.config.chunk_width();
+        return ChunkNeighbourhood::new(chunk_position, chunk_width, |block_position| {
+            let (chunk_position, local) = block_position.split(chunk_width);
+            let Some(chunk) = self.chunks.get(&chunk_position) else {
+                return (assumed_sunlight(&self.world_generator, block_position.to_ivec2()), false);
+            };
+            let index = local.index(chunk_width);
+            (chunk.light.level(index), casts_ambient_occlusion(self.block_registry.get(chunk.blocks(ChunkLayer::FOREGROUND).get(index))))
+        });
+    }
+
+    pub fn get_block(&self, block_position: BlockPos, layer: ChunkLayer) -> usize {
+        let (chunk_position, local) = block_position.split(self.config.chunk_width());
         let Some(chunk) = &self.chunks.get(&chunk_position) else {
             return 0;
         };
-        let relative_coords = get_relative_position(block_position, chunk_position);
-        return match layer {
-            ChunkLayer::FOREGROUND => chunk.foreground_blocks[get_index_from_position(relative_coords)],
-            ChunkLayer::BACKGROUND => chunk.background_blocks[get_index_from_position(relative_coords)]
+        return chunk.get_block(local, layer);
+    }
+
+    // The vertices of every chunk that was meshed since the last call, for the renderer.
+    pub fn take_mesh_updates(&mut self) -> HashMap<ChunkPos, ChunkVertices> {
+        return std::mem::take(&mut self.mesh_updates);
+    }
+
+    // Gives back vertices that aren't needed anymore, so remeshing can fill them instead of allocating.
+    pub fn recycle_vertices(&mut self, vertices: ChunkVertices) {
+        if self.spare_vertices.len() < MAX_SPARE_VERTICES {
+            self.spare_vertices.push(vertices);
         }
     }
 
+    pub fn pending_chunk_positions(&self) -> impl Iterator<Item = &ChunkPos> {
+        return self.pending_chunks.keys();
+    }
+
+    pub fn block_registry(&self) -> &BlockRegistry {
+        return &self.block_registry;
+    }
+
+    pub fn config(&self) -> &WorldConfig {
+        return &self.config;
+    }
+
+    pub fn world_generator(&self) -> &WorldGenerator {
+        return &self.world_generator;
+    }
+
     pub fn get_loaded_chunks_amount(&self) -> usize {
         return self.chunks.len();
     }
 
-    pub fn create_chunk(&mut self, chunk_position: IVec2, foreground_blocks: [usize; CHUNK_AREA], background_blocks: [usize; CHUNK_AREA]) {
+    pub fn is_chunk_loaded(&self, chunk_position: ChunkPos) -> bool {
+        return self.chunks.contains_key(&chunk_position);
+    }
+
+    fn integrate_chunk(&mut self, finished: FinishedChunk) {
+        // The chunk went off screen while it was being generated, or it was unloaded and requested
+        // again, then this result can be older than the edits that were saved in the meantime
+        if self.pending_chunks.get(&finished.position) != Some(&finished.generation) {
+            self.recycle_vertices(finished.vertices);
+            return;
+        }
+        self.pending_chunks.remove(&finished.position);
+
         self.chunks.insert(
-            chunk_position,
-            Chunk::new(chunk_position, foreground_blocks, background_blocks, self.blocks_atlas_texture.clone()),
+            finished.position,
+            Chunk::new(
+                finished.position,
+                self.config.chunk_width(),
+                &finished.foreground_blocks,
+                &finished.background_blocks,
+                finished.light,
+            ),
         );
+        if let Some(vertices) = self.mesh_updates.insert(finished.position, finished.vertices) {
+            self.recycle_vertices(vertices);
+        }
+
+        // The light of the new chunk and the chunks around it can be fixed only now that they're next to each other
+        let mut dirty_chunks = HashSet::new();
+        self.light_world(&mut dirty_chunks).stitch_chunk(finished.position);
+
+        // The tiles on the borders are shaded by the tiles on the other side, so both sides have to
+        // be meshed again. A chunk without neighbours keeps the mesh from the worker. This is done
+        // by the workers too, otherwise every new chunk would mean up to 9 meshes on the main thread.
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let neighbour = finished.position + ivec2(x, y);
+                if neighbour != finished.position && self.chunks.contains_key(&neighbour) {
+                    dirty_chunks.insert(neighbour);
+                    dirty_chunks.insert(finished.position);
+                }
+            }
+        }
+        self.remesh_chunks_on_workers(dirty_chunks);
     }
 
-    pub fn delete_chunk(&mut self, chunk_position: IVec2) {
-        self.chunks.remove(&chunk_position);
+    pub fn integrate_finished_chunks(&mut self) {
+        let mut integrated = 0;
+        // Remeshes only swap the vertices, so they don't count
+        while integrated < MAX_CHUNKS_INTEGRATED_PER_FRAME {
+            let Some(result) = self.workers.try_receive() else {
+                break;
+            };
+            match result {
+                WorkerResult::Loaded(finished) => {
+                    self.integrate_chunk(finished);
+                    integrated += 1;
+                }
+                WorkerResult::Remeshed(remeshed) => self.apply_remesh(remeshed),
+            }
+        }
+    }
+
+    // The chunk only reaches the disk when the world save gets flushed.
+    pub fn delete_chunk(&mut self, chunk_position: ChunkPos) {
+        if let Some(chunk) = self.chunks.remove(&chunk_position) {
+            self.save_chunk_if_modified(&chunk);
+        }
+        if let Some(vertices) = self.mesh_updates.remove(&chunk_position) {
+            self.recycle_vertices(vertices);
+        }
         self.chunks.shrink_to_fit();
     }
 
-    pub fn generate_chunk(&mut self, pos: IVec2) {
-        let mut foreground_blocks: [usize; 256] = [0; CHUNK_AREA];
-        let mut background_blocks: [usize; 256] = [0; CHUNK_AREA];
-
-        for y in 0..CHUNK_WIDTH {
-            for x in 0..CHUNK_WIDTH {
-                let index = get_index_from_position(uvec2(x as u32, y as u32));
-                let global_pos = ivec2(
-                    (pos.x * CHUNK_WIDTH as i32) + x as i32,
-                    (pos.y * CHUNK_WIDTH as i32) + y as i32,
-                );
-
-                let mut noise = HybridMulti::<Perlin>::default();
-                noise.frequency = 0.25;
-                noise.lacunarity = 3.0;
-
-                let s = ((noise.get([
-                    global_pos.x as f64 / CHUNK_WIDTH as f64,
-                    global_pos.x as f64 / CHUNK_WIDTH as f64,
-                    global_pos.x as f64 / CHUNK_WIDTH as f64,
-                ]) + 0.5)
-                    * CHUNK_WIDTH as f64)
-                    .round() as i32;
-
-                if global_pos.y == s {
-                    foreground_blocks[index] = 1;
-                    background_blocks[index] = 1;
-                } else if global_pos.y < s && global_pos.y >= s - 25 {
-                    foreground_blocks[index] = 2;
-                    background_blocks[index] = 2;
-                } else if global_pos.y < s - 25 {
-                    foreground_blocks[index] = 3;
-                    background_blocks[index] = 3;
-                }
+    // Loads the chunk from the world save if it was saved before, otherwise it gets
+    // generated from scratch. The chunk only appears once a worker finishes it.
+    pub fn request_chunk(&mut self, chunk_position: ChunkPos) {
+        if self.chunks.contains_key(&chunk_position) || self.pending_chunks.contains_key(&chunk_position) {
+            return;
+        }
 
-                if pos.y <= -3 {
-                    let cave_noise = Perlin::new(0);
-                    let sample = cave_noise.get([
-                        global_pos.x as f64 / CHUNK_WIDTH as f64,
-                        global_pos.y as f64 / CHUNK_WIDTH as f64,
-                    ]);
-                    if sample >= 0.5 {
-                        foreground_blocks[index] = 0;
-                    }
+        let generation = self.new_load_generation(chunk_position);
+        let job = self.get_chunk_job(chunk_position);
+        self.workers.submit(job, generation, self.meshing_mode);
+    }
+
+    // Same as request_chunk, but the chunk is loaded before returning, without the workers.
+    // Only meant for the few chunks that have to be changed while they're not loaded.
+    pub fn load_chunk_now(&mut self, chunk_position: ChunkPos) {
+        if self.chunks.contains_key(&chunk_position) {
+            return;
+        }
+        // If a worker is already doing this chunk, its result is dropped when it arrives
+        let generation = self.new_load_generation(chunk_position);
+        let job = self.get_chunk_job(chunk_position);
+        let finished = run_chunk_job(job, generation, self.meshing_mode, &self.world_generator, &self.block_registry);
+        self.integrate_chunk(finished);
+    }
+
+    fn new_load_generation(&mut self, chunk_position: ChunkPos) -> u64 {
+        let generation = self.next_load_generation;
+        self.next_load_generation += 1;
+        self.pending_chunks.insert(chunk_position, generation);
+        return generation;
+    }
+
+    fn get_chunk_job(&mut self, chunk_position: ChunkPos) -> ChunkJob {
+        return match self.world_save.load_chunk(chunk_position) {
+            Ok(Some((foreground_blocks, background_blocks))) => ChunkJob::Mesh(chunk_position, foreground_blocks, background_blocks),
+            Ok(None) => ChunkJob::Generate(chunk_position),
+            Err(e) => {
+                eprintln!("Failed to load chunk {}, generating it instead: {}", chunk_position, e);
+                ChunkJob::Generate(chunk_position)
+            }
+        };
+    }
+
+    // Should be called before closing the game, otherwise
+    // the chunks that are still loaded will lose their changes.
+    pub fn save_modified_chunks(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            if chunk.modified {
+                match self.world_save.save_chunk(chunk.position, &chunk.blocks(ChunkLayer::FOREGROUND).to_vec(), &chunk.blocks(ChunkLayer::BACKGROUND).to_vec()) {
+                    Ok(()) => chunk.modified = false,
+                    Err(e) => eprintln!("Failed to save chunk {}: {}", chunk.position, e),
                 }
             }
         }
+        self.flush_world_save();
+    }
 
-        self.create_chunk(pos, foreground_blocks, background_blocks);
+    fn save_chunk_if_modified(&mut self, chunk: &Chunk) {
+        if !chunk.modified {
+            return;
+        }
+        if let Err(e) = self.world_save.save_chunk(chunk.position, &chunk.blocks(ChunkLayer::FOREGROUND).to_vec(), &chunk.blocks(ChunkLayer::BACKGROUND).to_vec()) {
+            eprintln!("Failed to save chunk {}: {}", chunk.position, e);
+        }
     }
 
-    pub fn load_chunks_on_screen(&mut self, screen_aabb: &AxisAlignedRectangle) {
-        let RectangleCorners {
-            top_left,
-            bottom_right,
-            ..
-        } = screen_aabb.as_drectangle().corners();
+    fn flush_world_save(&mut self) {
+        if let Err(e) = self.world_save.flush() {
+            eprintln!("Failed to write the world save: {}", e);
+        }
+    }
 
-        let top_left_block = (top_left / TILE_SIZE as f32).floor().as_ivec2();
-        let bottom_right_block = (bottom_right / TILE_SIZE as f32).floor().as_ivec2();
+    // Loads the chunks around the screen and around the player, and unloads the ones that are far from both.
+    pub fn load_chunks_on_screen(&mut self, screen_aabb: &AxisAlignedRectangle, player_aabb: &AxisAlignedRectangle) {
+        let ChunkLoadingConfig {
+            load_margin,
+            unload_margin,
+            simulation_radius,
+        } = self.loading_config;
 
-        let top_left_chunk = get_chunk_position(top_left_block);
-        let bottom_right_chunk = get_chunk_position(bottom_right_block);
+        let screen_area = ChunkArea::from_aabb(screen_aabb, &self.config);
+        let simulation_area = ChunkArea::from_aabb(player_aabb, &self.config).expanded(simulation_radius);
 
-        for y in bottom_right_chunk.y..=top_left_chunk.y {
-            for x in top_left_chunk.x..=bottom_right_chunk.x {
-                let chunk = IVec2 { x, y };
-                if !self.chunks.contains_key(&chunk) {
-                    self.generate_chunk(chunk);
+        self.integrate_finished_chunks();
+
+        // The two areas can overlap, a set so the chunks in both are only requested once
+        let mut missing_chunks: HashSet<ChunkPos> = HashSet::new();
+        for area in [screen_area.expanded(load_margin), simulation_area] {
+            for y in area.min.0.y..=area.max.0.y {
+                for x in area.min.0.x..=area.max.0.x {
+                    let chunk = ChunkPos::new(x, y);
+                    if !self.chunks.contains_key(&chunk) && !self.pending_chunks.contains_key(&chunk) {
+                        missing_chunks.insert(chunk);
+                    }
                 }
             }
         }
 
-        self.unload_unseen_chunks(screen_aabb);
+        // The chunks closer to the center of the screen are requested first, and the
+        // position breaks ties so the order doesn't depend on the set
+        let center_chunk = BlockPos::from_world(screen_aabb.center_pos, self.config.tile_size()).chunk(self.config.chunk_width());
+        let mut missing_chunks: Vec<ChunkPos> = missing_chunks.into_iter().collect();
+        missing_chunks.sort_by_key(|chunk| ((chunk.0 - center_chunk.0).length_squared(), chunk.0.y, chunk.0.x));
+        for chunk in missing_chunks {
+            self.request_chunk(chunk);
+        }
+
+        // Chunks are only unloaded further away than where they get loaded, so a
+        // chunk right at the edge of the screen doesn't get loaded and unloaded every frame.
+        self.unload_distant_chunks(&[screen_area.expanded(unload_margin), simulation_area]);
     }
 
-    pub fn unload_unseen_chunks(&mut self, screen_aabb: &AxisAlignedRectangle) {
-        let mut chunk_poses_to_delete: Vec<IVec2> = vec![];
-        for (chunk_pos, chunk) in self.chunks.iter() {
-            if !screen_aabb.intersects(&chunk.aabb) {
-                chunk_poses_to_delete.push(*chunk_pos);
-            }
+    // Unloads every chunk that is outside all of these areas.
+    pub fn unload_distant_chunks(&mut self, keep_areas: &[ChunkArea]) {
+        let should_keep = |pos: &ChunkPos| keep_areas.iter().any(|area| area.contains(*pos));
+
+        let chunk_poses_to_delete: Vec<ChunkPos> = self
+            .chunks
+            .keys()
+            .filter(|pos| !should_keep(pos))
+            .copied()
+            .collect();
+
+        // The chunks that are still being generated are dropped as soon as they finish
+        self.pending_chunks.retain(|pos, _| should_keep(pos));
+
+        if chunk_poses_to_delete.is_empty() {
+            return;
         }
 
         for pos in chunk_poses_to_delete {
             self.delete_chunk(pos);
         }
+        self.flush_world_save();
+        // Otherwise every region the player has been to would stay in memory
+        self.world_save.unload_unused_regions(self.chunks.keys().chain(self.pending_chunks.keys()).copied());
+    }
+}
+
+// How far away from the screen and the player chunks are kept loaded, in chunks.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkLoadingConfig {
+    // Chunks this far outside the screen get loaded
+    pub load_margin: i32,
+    // Chunks only get unloaded once they are this far outside the screen,
+    // it's always at least as big as load_margin
+    pub unload_margin: i32,
+    // Chunks this close to the player are always loaded, no matter where the camera is
+    pub simulation_radius: i32,
+}
+
+impl Default for ChunkLoadingConfig {
+    fn default() -> Self {
+        ChunkLoadingConfig {
+            load_margin: 1,
+            unload_margin: 3,
+            simulation_radius: 1,
+        }
     }
 }
 
-pub fn get_chunk_position(block_position: IVec2) -> IVec2 {
-    return IVec2::new(
-        (block_position.x as f32 / CHUNK_WIDTH as f32).floor() as i32,
-        (block_position.y as f32 / CHUNK_WIDTH as f32).floor() as i32,
-    );
+impl ChunkLoadingConfig {
+    fn validated(self) -> ChunkLoadingConfig {
+        let load_margin = self.load_margin.max(0);
+        return ChunkLoadingConfig {
+            load_margin,
+            unload_margin: self.unload_margin.max(load_margin),
+            simulation_radius: self.simulation_radius.max(0),
+        };
+    }
 }
 
-pub fn get_relative_position(global_position: IVec2, chunk_position: IVec2) -> UVec2 {
-    return UVec2::new(
-        (global_position.x as f32 - (chunk_position.x as f32 * CHUNK_WIDTH as f32)) as u32,
-        (global_position.y as f32 - (chunk_position.y as f32 * CHUNK_WIDTH as f32)) as u32,
-    );
+// A rectangle of chunk positions, both corners are included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkArea {
+    pub min: ChunkPos,
+    pub max: ChunkPos,
 }
 
-pub fn get_index_from_position(position: UVec2) -> usize {
-    return position.x as usize + (position.y as usize * CHUNK_WIDTH);
+impl ChunkArea {
+    // Every chunk that intersects this rectangle in world units
+    pub fn from_aabb(aabb: &AxisAlignedRectangle, config: &WorldConfig) -> ChunkArea {
+        let RectangleCorners {
+            bottom_left,
+            top_right,
+            ..
+        } = aabb.as_drectangle().corners();
+
+        return ChunkArea {
+            min: BlockPos::from_world(bottom_left, config.tile_size()).chunk(config.chunk_width()),
+            max: BlockPos::from_world(top_right, config.tile_size()).chunk(config.chunk_width()),
+        };
+    }
+
+    pub fn expanded(&self, margin: i32) -> ChunkArea {
+        return ChunkArea {
+            min: self.min + IVec2::splat(-margin),
+            max: self.max + IVec2::splat(margin),
+        };
+    }
+
+    pub fn contains(&self, chunk_position: ChunkPos) -> bool {
+        return chunk_position.0.cmpge(self.min.0).all() && chunk_position.0.cmple(self.max.0).all();
+    }
+}
+
+pub fn get_chunk_aabb(chunk_position: ChunkPos, config: &WorldConfig) -> AxisAlignedRectangle {
+    let size = Vec2::splat(config.chunk_world_width());
+    return AxisAlignedRectangle {
+        center_pos: chunk_position.0.as_vec2() * config.chunk_world_width() + size * 0.5,
+        size,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_area_covers_the_aabb() {
+        for config in [WorldConfig::default(), WorldConfig::new(5, 12).unwrap(), WorldConfig::new(128, 8).unwrap()] {
+            let aabb = AxisAlignedRectangle {
+                center_pos: Vec2::ZERO,
+                size: Vec2::new(config.chunk_world_width(), 10.0),
+            };
+            let area = ChunkArea::from_aabb(&aabb, &config);
+            assert_eq!(area, ChunkArea { min: ChunkPos::new(-1, -1), max: ChunkPos::new(0, 0) });
+
+            let expanded = area.expanded(2);
+            assert!(expanded.contains(ChunkPos::new(-3, 2)));
+            assert!(!expanded.contains(ChunkPos::new(-4, 0)));
+            assert!(!expanded.contains(ChunkPos::new(0, 3)));
+
+            // The area of a chunk is the same chunk again
+            assert_eq!(ChunkArea::from_aabb(&get_chunk_aabb(ChunkPos::new(3, -2), &config), &config).min, ChunkPos::new(3, -2));
+        }
+    }
+
+    #[test]
+    fn unload_margin_is_never_smaller_than_load_margin() {
+        let config = ChunkLoadingConfig {
+            load_margin: 4,
+            unload_margin: 2,
+            simulation_radius: -1,
+        }
+        .validated();
+        assert_eq!(config.unload_margin, 4);
+        assert_eq!(config.simulation_radius, 0);
+    }
 }
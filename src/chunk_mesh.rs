@@ -0,0 +1,379 @@
+use macroquad::models::Vertex;
+use macroquad::prelude::*;
+
+use crate::{
+    block_registry::{BlockProperties, BlockRegistry},
+    coordinates::{BlockPos, ChunkPos, LocalPos},
+    lighting::light_brightness,
+    world_config::WorldConfig,
+};
+
+// The background is always drawn darker than the foreground, so both layers can be told apart
+const BACKGROUND_SHADE: f32 = 0.5;
+// How dark a corner of the background gets, by the amount of foreground blocks around it
+const AMBIENT_OCCLUSION: [f32; 5] = [1.0, 0.75, 0.6, 0.5, 0.5];
+
+// The vertices of both layers of a chunk. They are only data, so they can be
+// built without a window, the ChunkRenderer turns them into meshes.
+// Only the tiles that can be seen get a quad, 4 vertices each, so the sky has no vertices at all.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkVertices {
+    pub foreground: Vec<Vertex>,
+    pub background: Vec<Vertex>,
+}
+
+impl ChunkVertices {
+    pub fn new() -> ChunkVertices {
+        return ChunkVertices::default();
+    }
+
+    // Keeps the memory of both lists, so they can be filled again without allocating.
+    pub fn clear(&mut self) {
+        self.foreground.clear();
+        self.background.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.foreground.is_empty() && self.background.is_empty();
+    }
+}
+
+// The light of a chunk and of the ring of tiles around it, plus which of those
+// tiles have a foreground block that casts a shadow on the background.
+// Every corner of a tile is shared with 3 other tiles, so meshing
+// the tiles on the border of the chunk needs its neighbours too.
+pub struct ChunkNeighbourhood {
+    position: ChunkPos,
+    // The chunk width plus the ring on both sides
+    padded_width: usize,
+    light: Vec<u8>,
+    occluders: Vec<bool>,
+}
+
+impl ChunkNeighbourhood {
+    // Asks sample for the (light level, occludes) of every global block position in and around the chunk.
+    pub fn new(position: ChunkPos, chunk_width: usize, sample: impl Fn(BlockPos) -> (u8, bool)) -> ChunkNeighbourhood {
+        let padded_width = chunk_width + 2;
+        let mut neighbourhood = ChunkNeighbourhood {
+            position,
+            padded_width,
+            light: vec![0; padded_width * padded_width],
+            occluders: vec![false; padded_width * padded_width],
+        };
+        let origin = neighbourhood.position.origin(chunk_width) + IVec2::NEG_ONE;
+        for y in 0..padded_width {
+            for x in 0..padded_width {
+                let (light, occludes) = sample(origin + ivec2(x as i32, y as i32));
+                neighbourhood.light[x + y * padded_width] = light;
+                neighbourhood.occluders[x + y * padded_width] = occludes;
+            }
+        }
+        return neighbourhood;
+    }
+
+    // The brightness and the ambient occlusion of the corner at the bottom left of the tile
+    // (x, y) of the chunk, where x and y go from 0 to the chunk width, both included.
+    fn corner(&self, x: usize, y: usize) -> (f32, f32) {
+        let around = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)].map(|(x, y)| x + y * self.padded_width);
+        let brightness = around.iter().map(|&i| light_brightness(self.light[i])).sum::<f32>() / 4.0;
+        let occluders = around.iter().filter(|&&i| self.occluders[i]).count();
+        return (brightness, AMBIENT_OCCLUSION[occluders]);
+    }
+}
+
+// Foreground blocks that the background can't be seen through cast a shadow on it.
+pub fn casts_ambient_occlusion(block: &BlockProperties) -> bool {
+    return !block.transparent;
+}
+
+// How the tiles of a chunk are turned into quads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshingMode {
+    // A quad for every tile
+    SIMPLE,
+    // Tiles next to each other with the same block and the same light everywhere
+    // are merged into bigger quads, the texture is repeated over them by the chunk material.
+    // Much fewer vertices underground, where everything is stone in the dark.
+    GREEDY,
+}
+
+impl MeshingMode {
+    pub fn next(&self) -> MeshingMode {
+        return match self {
+            MeshingMode::SIMPLE => MeshingMode::GREEDY,
+            MeshingMode::GREEDY => MeshingMode::SIMPLE,
+        };
+    }
+}
+
+impl std::fmt::Display for MeshingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshingMode::SIMPLE => write!(f, "Simple"),
+            MeshingMode::GREEDY => write!(f, "Greedy"),
+        }
+    }
+}
+
+// A tile that can be seen, with the colors of its corners in the same order as the vertices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TileQuad {
+    atlas_index: usize,
+    colors: [[u8; 4]; 4],
+}
+
+impl TileQuad {
+    // Only tiles that are the same color everywhere can be merged, otherwise
+    // the colors wouldn't be blended between the corners of every tile anymore.
+    fn is_flat(&self) -> bool {
+        return self.colors.iter().all(|color| *color == self.colors[0]);
+    }
+}
+
+// Replaces whatever was in vertices with the quads of the chunk of the neighbourhood. Passing the
+// vertices of a chunk that was meshed before reuses their memory.
+//
+// The UVs of the vertices are in tiles, starting at 0 at the bottom left of the quad,
+// and the first two values of the normal are where the texture of the block starts in
+// the atlas and how wide it is. The chunk material turns them into atlas UVs.
+//
+// It doesn't need the chunk itself, so it can be run on the chunk worker threads.
+pub fn build_chunk_vertices(
+    foreground_blocks: &[usize],
+    background_blocks: &[usize],
+    neighbourhood: &ChunkNeighbourhood,
+    block_registry: &BlockRegistry,
+    config: &WorldConfig,
+    meshing_mode: MeshingMode,
+    vertices: &mut ChunkVertices,
+) {
+    vertices.clear();
+    let chunk_width = config.chunk_width();
+    let tile_size = config.tile_size();
+    let mut foreground_quads: Vec<Option<TileQuad>> = vec![None; config.chunk_area()];
+    let mut background_quads: Vec<Option<TileQuad>> = vec![None; config.chunk_area()];
+
+    for y in 0..chunk_width {
+        for x in 0..chunk_width {
+            let index: usize = x + (y * chunk_width);
+            let foreground_block = block_registry.get(foreground_blocks[index]);
+            // The background can only be seen through a transparent foreground block
+            let background_atlas_index = match foreground_block.transparent {
+                true => block_registry.get(background_blocks[index]).atlas_index,
+                false => None,
+            };
+
+            // In the same order as the vertices
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)].map(|(x, y)| neighbourhood.corner(x, y));
+            let tile_quad = |atlas_index: usize, is_background: bool| {
+                let colors = corners.map(|(brightness, occlusion)| {
+                    let shade = match is_background {
+                        true => brightness * BACKGROUND_SHADE * occlusion,
+                        false => brightness,
+                    };
+                    Color::new(shade, shade, shade, 1.0).into()
+                });
+                TileQuad { atlas_index, colors }
+            };
+
+            // Blocks without a texture, like air, don't get a quad
+            foreground_quads[index] = foreground_block.atlas_index.map(|atlas_index| tile_quad(atlas_index, false));
+            background_quads[index] = background_atlas_index.map(|atlas_index| tile_quad(atlas_index, true));
+        }
+    }
+
+    let origin = neighbourhood.position.origin(chunk_width).to_ivec2().as_vec2() * tile_size;
+    let block_uv_unit = 1.0 / block_registry.atlas_columns() as f32;
+    for (quads, layer_vertices) in [(&foreground_quads, &mut vertices.foreground), (&background_quads, &mut vertices.background)] {
+        let mut push_quad = |quad: &TileQuad, tile: UVec2, size: UVec2| {
+            let bottom_left = origin + tile.as_vec2() * tile_size;
+            let size = size.as_vec2();
+            let atlas_uv = vec4(block_uv_unit * quad.atlas_index as f32, block_uv_unit, 0.0, 0.0);
+            let offsets = [vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)];
+            for (offset, color) in offsets.iter().zip(quad.colors.iter()) {
+                layer_vertices.push(Vertex {
+                    position: (bottom_left + *offset * size * tile_size).extend(0.0),
+                    uv: *offset * size,
+                    color: *color,
+                    normal: atlas_uv,
+                });
+            }
+        };
+
+        match meshing_mode {
+            MeshingMode::SIMPLE => {
+                for (index, quad) in quads.iter().enumerate() {
+                    if let Some(quad) = quad {
+                        let tile = LocalPos::from_index(index, chunk_width);
+                        push_quad(quad, uvec2(tile.x(), tile.y()), UVec2::ONE);
+                    }
+                }
+            }
+            MeshingMode::GREEDY => {
+                for (quad, tile, size) in greedy_quads(quads, chunk_width) {
+                    push_quad(&quad, tile, size);
+                }
+            }
+        }
+    }
+}
+
+// Merges the flat tiles into rectangles, going right first and then up as far as every
+// tile of the row is the same. Returns every quad with its bottom left tile and its size in tiles.
+fn greedy_quads(quads: &[Option<TileQuad>], chunk_width: usize) -> Vec<(TileQuad, UVec2, UVec2)> {
+    let mut merged: Vec<(TileQuad, UVec2, UVec2)> = vec![];
+    let mut done = vec![false; quads.len()];
+    let index = |x: usize, y: usize| x + y * chunk_width;
+
+    for y in 0..chunk_width {
+        for x in 0..chunk_width {
+            let Some(quad) = quads[index(x, y)] else {
+                continue;
+            };
+            if done[index(x, y)] {
+                continue;
+            }
+            if !quad.is_flat() {
+                done[index(x, y)] = true;
+                merged.push((quad, uvec2(x as u32, y as u32), UVec2::ONE));
+                continue;
+            }
+
+            let mergeable = |x: usize, y: usize| !done[index(x, y)] && quads[index(x, y)] == Some(quad);
+            let mut width = 1;
+            while x + width < chunk_width && mergeable(x + width, y) {
+                width += 1;
+            }
+            let mut height = 1;
+            while y + height < chunk_width && (x..x + width).all(|x| mergeable(x, y + height)) {
+                height += 1;
+            }
+
+            for y in y..y + height {
+                for x in x..x + width {
+                    done[index(x, y)] = true;
+                }
+            }
+            merged.push((quad, uvec2(x as u32, y as u32), uvec2(width as u32, height as u32)));
+        }
+    }
+    return merged;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lighting::MAX_LIGHT;
+
+    // Odd sizes too, so nothing assumes a power of two
+    const TEST_SIZES: [(usize, usize); 4] = [(16, 32), (4, 32), (33, 10), (128, 8)];
+
+    #[test]
+    fn corners_are_shared_with_the_neighbouring_chunks() {
+        // Fully lit, with a dark block and a shadow just outside of the bottom left of the chunk
+        let neighbourhood = ChunkNeighbourhood::new(ChunkPos::new(2, -1), 16, |pos| match pos == BlockPos::new(31, -17) {
+            true => (0, true),
+            false => (MAX_LIGHT, false),
+        });
+
+        let (brightness, occlusion) = neighbourhood.corner(0, 0);
+        assert_eq!(brightness, (light_brightness(0) + 3.0 * light_brightness(MAX_LIGHT)) / 4.0);
+        assert_eq!(occlusion, AMBIENT_OCCLUSION[1]);
+
+        assert_eq!(neighbourhood.corner(1, 0), (light_brightness(MAX_LIGHT), AMBIENT_OCCLUSION[0]));
+        assert_eq!(neighbourhood.corner(16, 16), (light_brightness(MAX_LIGHT), AMBIENT_OCCLUSION[0]));
+    }
+
+    #[test]
+    fn only_visible_tiles_get_quads() {
+        let block_registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let stone = block_registry.id_by_name("stone").unwrap();
+        let glass = block_registry.id_by_name("glass").unwrap();
+        for (chunk_width, tile_size) in TEST_SIZES {
+            let config = WorldConfig::new(chunk_width, tile_size).unwrap();
+            let area = config.chunk_area();
+            let neighbourhood = ChunkNeighbourhood::new(ChunkPos::new(0, 0), chunk_width, |_| (MAX_LIGHT, false));
+            let mut vertices = ChunkVertices::new();
+
+            build_chunk_vertices(&vec![0; area], &vec![0; area], &neighbourhood, &block_registry, &config, MeshingMode::SIMPLE, &mut vertices);
+            assert!(vertices.is_empty());
+
+            // Stone hides the background behind it, glass doesn't
+            let mut foreground_blocks = vec![0; area];
+            foreground_blocks[0] = stone;
+            foreground_blocks[1] = glass;
+            build_chunk_vertices(&foreground_blocks, &vec![stone; area], &neighbourhood, &block_registry, &config, MeshingMode::SIMPLE, &mut vertices);
+            assert_eq!(vertices.foreground.len(), 2 * 4);
+            assert_eq!(vertices.background.len(), (area - 1) * 4);
+            assert_eq!(vertices.foreground[4].position, vec3(tile_size as f32, 0.0, 0.0));
+            // Every vertex of a full chunk still fits in the u16 indices of a mesh
+            assert!(vertices.background.len() <= u16::MAX as usize + 1);
+
+            // Meshing again reuses the memory of the last mesh
+            let capacity = vertices.background.capacity();
+            build_chunk_vertices(&foreground_blocks, &vec![0; area], &neighbourhood, &block_registry, &config, MeshingMode::SIMPLE, &mut vertices);
+            assert!(vertices.background.is_empty());
+            assert_eq!(vertices.background.capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn greedy_meshing_only_merges_tiles_with_the_same_light() {
+        let block_registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let stone = block_registry.id_by_name("stone").unwrap();
+        let dirt = block_registry.id_by_name("dirt").unwrap();
+        let config = WorldConfig::default();
+        let (chunk_width, area) = (config.chunk_width(), config.chunk_area());
+        let mut foreground_blocks = vec![stone; area];
+        foreground_blocks[LocalPos::new(3, 3, chunk_width).index(chunk_width)] = dirt;
+        let all_stone = vec![stone; area];
+        let mut vertices = ChunkVertices::new();
+
+        let dark = ChunkNeighbourhood::new(ChunkPos::new(0, -4), chunk_width, |_| (0, true));
+        build_chunk_vertices(&all_stone, &all_stone, &dark, &block_registry, &config, MeshingMode::GREEDY, &mut vertices);
+        assert_eq!(vertices.foreground.len(), 4);
+        assert_eq!(vertices.foreground[2].uv, vec2(chunk_width as f32, chunk_width as f32));
+
+        // The dirt block is cut out of the stone
+        build_chunk_vertices(&foreground_blocks, &all_stone, &dark, &block_registry, &config, MeshingMode::GREEDY, &mut vertices);
+        let mut merged_area = 0.0;
+        for quad in vertices.foreground.chunks(4) {
+            let size = quad[2].uv;
+            merged_area += size.x * size.y;
+        }
+        assert_eq!(merged_area, area as f32);
+        assert!(vertices.foreground.len() < area * 4 / 10);
+
+        // A light in the middle of the chunk makes the tiles around it different from each other
+        let lit = ChunkNeighbourhood::new(ChunkPos::new(0, -4), chunk_width, |pos| match pos == BlockPos::new(8, -56) {
+            true => (MAX_LIGHT, true),
+            false => (0, true),
+        });
+        build_chunk_vertices(&all_stone, &all_stone, &lit, &block_registry, &config, MeshingMode::GREEDY, &mut vertices);
+        let mut simple = ChunkVertices::new();
+        build_chunk_vertices(&all_stone, &all_stone, &lit, &block_registry, &config, MeshingMode::SIMPLE, &mut simple);
+        // The 8 tiles around the light only have some of their corners lit, so they keep their own quads
+        let lit_tiles = simple.foreground.chunks(4).filter(|quad| quad.iter().any(|vertex| vertex.color != quad[0].color)).count();
+        assert_eq!(lit_tiles, 8);
+        let unmerged = vertices.foreground.chunks(4).filter(|quad| quad[2].uv == Vec2::ONE).count();
+        assert!(unmerged >= lit_tiles);
+        assert!(vertices.foreground.len() < simple.foreground.len());
+    }
+
+    #[test]
+    fn greedy_quads_cover_chunks_of_every_size() {
+        let block_registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let stone = block_registry.id_by_name("stone").unwrap();
+        for (chunk_width, tile_size) in TEST_SIZES {
+            let config = WorldConfig::new(chunk_width, tile_size).unwrap();
+            let all_stone = vec![stone; config.chunk_area()];
+            let dark = ChunkNeighbourhood::new(ChunkPos::new(-1, -1), chunk_width, |_| (0, true));
+            let mut vertices = ChunkVertices::new();
+            build_chunk_vertices(&all_stone, &all_stone, &dark, &block_registry, &config, MeshingMode::GREEDY, &mut vertices);
+            assert_eq!(vertices.foreground.len(), 4);
+            // The single quad covers the whole chunk, ending at the origin of the world
+            assert_eq!(vertices.foreground[0].position, vec3(-config.chunk_world_width(), -config.chunk_world_width(), 0.0));
+            assert_eq!(vertices.foreground[2].position, Vec3::ZERO);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use macroquad::{
+    miniquad::{BlendFactor, BlendState, BlendValue, Equation},
+    prelude::*,
+};
+
+use crate::{
+    chunk_manager::{get_chunk_aabb, ChunkManager},
+    collision::bounding_box::AxisAlignedRectangle,
+    coordinates::ChunkPos,
+    world_config::WorldConfig,
+};
+
+// The UVs of the chunk vertices are in tiles, so a quad that covers many tiles repeats
+// the texture of its block on every one of them, see build_chunk_vertices.
+const CHUNK_VERTEX_SHADER: &str = r#"#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+attribute vec4 normal;
+
+varying mediump vec2 tile_uv;
+varying mediump vec2 atlas;
+varying lowp vec4 color;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    tile_uv = texcoord;
+    atlas = normal.xy;
+}"#;
+
+const CHUNK_FRAGMENT_SHADER: &str = r#"#version 100
+varying mediump vec2 tile_uv;
+varying mediump vec2 atlas;
+varying lowp vec4 color;
+
+uniform sampler2D Texture;
+
+void main() {
+    // The top of the texture is at v = 0, but y goes up in the world
+    vec2 uv = vec2(atlas.x + fract(tile_uv.x) * atlas.y, 1.0 - fract(tile_uv.y));
+    gl_FragColor = color * texture2D(Texture, uv);
+}"#;
+
+struct ChunkMeshes {
+    foreground: Mesh,
+    background: Mesh,
+    aabb: AxisAlignedRectangle,
+}
+
+// Draws the chunks of a chunk manager. It needs a window, since it has the
+// texture atlas, so everything else about the world can work without one.
+pub struct ChunkRenderer {
+    config: WorldConfig,
+    blocks_atlas_texture: Texture2D,
+    material: Material,
+    meshes: HashMap<ChunkPos, ChunkMeshes>,
+    // The indices of a chunk where every tile has a quad, a chunk
+    // with fewer quads uses the start of it
+    indices: Vec<u16>,
+}
+
+impl ChunkRenderer {
+    pub async fn load(config: WorldConfig) -> ChunkRenderer {
+        let blocks_atlas_texture = load_texture("assets/textures/blocks.png").await.unwrap();
+        blocks_atlas_texture.set_filter(FilterMode::Nearest);
+        let material = load_material(
+            ShaderSource::Glsl {
+                vertex: CHUNK_VERTEX_SHADER,
+                fragment: CHUNK_FRAGMENT_SHADER,
+            },
+            MaterialParams {
+                // Blended like everything else, for the blocks with see-through parts
+                pipeline_params: PipelineParams {
+                    color_blend: Some(BlendState::new(
+                        Equation::Add,
+                        BlendFactor::Value(BlendValue::SourceAlpha),
+                        BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+                    )),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // The config makes sure that every vertex of a chunk can be reached with u16 indices,
+        // and main.rs makes the draw calls big enough for the biggest chunks
+        let mut indices = vec![0; config.chunk_area() * 6];
+        let mut offset: usize = 0;
+        // The zeros keep the six indices of a quad lined up, clippy would have them removed
+        #[allow(clippy::identity_op)]
+        for i in (0..(config.chunk_area() * 6)).step_by(6) {
+            indices[i + 0] = 0 + offset as u16;
+            indices[i + 1] = 1 + offset as u16;
+            indices[i + 2] = 2 + offset as u16;
+
+            indices[i + 3] = 2 + offset as u16;
+            indices[i + 4] = 3 + offset as u16;
+            indices[i + 5] = 0 + offset as u16;
+
+            offset += 4;
+        }
+
+        return ChunkRenderer {
+            config,
+            blocks_atlas_texture,
+            material,
+            meshes: HashMap::new(),
+            indices,
+        };
+    }
+
+    pub fn blocks_atlas_texture(&self) -> &Texture2D {
+        return &self.blocks_atlas_texture;
+    }
+
+    // Picks up the chunks that were meshed since the last update, and
+    // forgets the meshes of the chunks that were unloaded.
+    pub fn update(&mut self, chunk_manager: &mut ChunkManager) {
+        for (chunk_position, mut vertices) in chunk_manager.take_mesh_updates() {
+            let meshes = self.meshes.entry(chunk_position).or_insert_with(|| {
+                let empty_mesh = || Mesh {
+                    vertices: vec![],
+                    indices: vec![],
+                    texture: Some(self.blocks_atlas_texture.clone()),
+                };
+                ChunkMeshes {
+                    foreground: empty_mesh(),
+                    background: empty_mesh(),
+                    aabb: get_chunk_aabb(chunk_position, &self.config),
+                }
+            });
+            // The old vertices go back to the chunk manager, to be filled by a later remesh
+            std::mem::swap(&mut meshes.foreground.vertices, &mut vertices.foreground);
+            std::mem::swap(&mut meshes.background.vertices, &mut vertices.background);
+            for mesh in [&mut meshes.foreground, &mut meshes.background] {
+                let index_count = mesh.vertices.len() / 4 * 6;
+                mesh.indices.clear();
+                mesh.indices.extend_from_slice(&self.indices[..index_count]);
+            }
+            chunk_manager.recycle_vertices(vertices);
+        }
+
+        self.meshes.retain(|chunk_position, _| chunk_manager.is_chunk_loaded(*chunk_position));
+    }
+
+    pub fn draw(&self, chunk_manager: &ChunkManager, screen_aabb: &AxisAlignedRectangle, debug: bool) {
+        // Only render chunks that are inside the screen!!!
+        // ================================================
+        let visible_meshes = self.meshes.iter().filter(|(_, meshes)| screen_aabb.intersects(&meshes.aabb));
+        gl_use_material(&self.material);
+        for (_, meshes) in visible_meshes.clone() {
+            // Chunks of sky don't have anything to draw
+            if !meshes.background.vertices.is_empty() {
+                draw_mesh(&meshes.background);
+            }
+            if !meshes.foreground.vertices.is_empty() {
+                draw_mesh(&meshes.foreground);
+            }
+        }
+        gl_use_default_material();
+
+        if debug {
+            for (chunk_position, _) in visible_meshes {
+                draw_chunk_grid(*chunk_position, &self.config);
+            }
+        }
+
+        // Chunks that are still being generated are drawn as a placeholder
+        let chunk_pixel_size = self.config.chunk_world_width();
+        for pos in chunk_manager.pending_chunk_positions() {
+            let chunk_aabb = get_chunk_aabb(*pos, &self.config);
+            if screen_aabb.intersects(&chunk_aabb) {
+                draw_rectangle(
+                    pos.0.x as f32 * chunk_pixel_size,
+                    pos.0.y as f32 * chunk_pixel_size,
+                    chunk_pixel_size,
+                    chunk_pixel_size,
+                    color_u8!(0.0, 0.0, 0.0, 64.0),
+                );
+            }
+        }
+    }
+}
+
+fn draw_chunk_grid(chunk_position: ChunkPos, config: &WorldConfig) {
+    let chunk_pixel_size = config.chunk_world_width();
+    let tile_size = config.tile_size();
+    let position = chunk_position.0;
+    for y in 0..config.chunk_width() {
+        draw_line(
+            position.x as f32 * chunk_pixel_size,
+            (position.y as f32 * chunk_pixel_size) + (y as f32 * tile_size),
+            (position.x as f32 * chunk_pixel_size) + chunk_pixel_size,
+            (position.y as f32 * chunk_pixel_size) + (y as f32 * tile_size),
+            2.0,
+            color_u8!(255.0, 255.0, 255.0, 128.0),
+        );
+    }
+    for x in 0..config.chunk_width() {
+        draw_line(
+            (position.x as f32 * chunk_pixel_size) + (x as f32 * tile_size),
+            position.y as f32 * chunk_pixel_size,
+            (position.x as f32 * chunk_pixel_size) + (x as f32 * tile_size),
+            (position.y as f32 * chunk_pixel_size) + chunk_pixel_size,
+            2.0,
+            color_u8!(255.0, 255.0, 255.0, 128.0),
+        );
+    }
+
+    draw_line(
+        position.x as f32 * chunk_pixel_size,
+        position.y as f32 * chunk_pixel_size,
+        (position.x as f32 * chunk_pixel_size) + chunk_pixel_size,
+        position.y as f32 * chunk_pixel_size,
+        5.0,
+        BLUE,
+    );
+    draw_line(
+        position.x as f32 * chunk_pixel_size,
+        position.y as f32 * chunk_pixel_size,
+        position.x as f32 * chunk_pixel_size,
+        (position.y as f32 * chunk_pixel_size) + chunk_pixel_size,
+        5.0,
+        RED,
+    );
+}
@@ -0,0 +1,197 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    block_registry::BlockRegistry,
+    chunk_mesh::{build_chunk_vertices, casts_ambient_occlusion, ChunkNeighbourhood, ChunkVertices, MeshingMode},
+    coordinates::ChunkPos,
+    lighting::{assumed_sunlight, compute_chunk_light, ChunkLight},
+    world_config::WorldConfig,
+    world_generator::WorldGenerator,
+};
+
+pub enum ChunkJob {
+    // Generates the blocks of a chunk that was never saved, then builds its vertices
+    Generate(ChunkPos),
+    // Only builds the vertices of a chunk that already has blocks, like the ones loaded from the world save
+    Mesh(ChunkPos, Vec<usize>, Vec<usize>),
+}
+
+// Builds the vertices of a chunk that is already loaded again, with the light and shading of
+// the chunks around it, which the main thread copied into the neighbourhood.
+pub struct RemeshJob {
+    pub position: ChunkPos,
+    // The mesh revision the chunk got when this job was sent, if the chunk was meshed
+    // again by the time this finishes, the result is outdated and thrown away
+    pub revision: u64,
+    pub foreground_blocks: Vec<usize>,
+    pub background_blocks: Vec<usize>,
+    pub neighbourhood: ChunkNeighbourhood,
+    // Vertices that aren't used anymore, to be filled instead of allocating new ones
+    pub vertices: ChunkVertices,
+}
+
+pub struct RemeshedChunk {
+    pub position: ChunkPos,
+    pub revision: u64,
+    pub vertices: ChunkVertices,
+}
+
+enum WorkerJob {
+    // With the generation of the request
+    Load(ChunkJob, u64),
+    Remesh(RemeshJob),
+}
+
+pub enum WorkerResult {
+    Loaded(FinishedChunk),
+    Remeshed(RemeshedChunk),
+}
+
+pub struct FinishedChunk {
+    pub position: ChunkPos,
+    // The generation the chunk manager gave the request, see ChunkManager::request_chunk
+    pub generation: u64,
+    pub foreground_blocks: Vec<usize>,
+    pub background_blocks: Vec<usize>,
+    pub vertices: ChunkVertices,
+    // Only correct for this chunk on its own, the chunk manager fixes the borders once it's added
+    pub light: ChunkLight,
+}
+
+// Threads that generate and mesh chunks in the background, so
+// the main thread only has to add them to the world when they are done.
+pub struct ChunkWorkerPool {
+    job_sender: Sender<(WorkerJob, MeshingMode)>,
+    finished_receiver: Receiver<WorkerResult>,
+    _workers: Vec<JoinHandle<()>>,
+}
+
+impl ChunkWorkerPool {
+    pub fn new(world_generator: Arc<WorldGenerator>, block_registry: Arc<BlockRegistry>) -> ChunkWorkerPool {
+        // One core is left for the main thread
+        let worker_count = thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(1))
+            .unwrap_or(1)
+            .clamp(1, 4);
+
+        let (job_sender, job_receiver) = mpsc::channel::<(WorkerJob, MeshingMode)>();
+        let (finished_sender, finished_receiver) = mpsc::channel::<WorkerResult>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..worker_count)
+            .map(|i| {
+                let job_receiver = job_receiver.clone();
+                let finished_sender = finished_sender.clone();
+                let world_generator = world_generator.clone();
+                let block_registry = block_registry.clone();
+                thread::Builder::new()
+                    .name(format!("chunk worker {}", i))
+                    .spawn(move || worker_loop(job_receiver, finished_sender, world_generator, block_registry))
+                    .unwrap()
+            })
+            .collect();
+
+        ChunkWorkerPool {
+            job_sender,
+            finished_receiver,
+            _workers: workers,
+        }
+    }
+
+    pub fn submit(&self, job: ChunkJob, generation: u64, meshing_mode: MeshingMode) {
+        self.send(WorkerJob::Load(job, generation), meshing_mode);
+    }
+
+    pub fn submit_remesh(&self, job: RemeshJob, meshing_mode: MeshingMode) {
+        self.send(WorkerJob::Remesh(job), meshing_mode);
+    }
+
+    fn send(&self, job: WorkerJob, meshing_mode: MeshingMode) {
+        // This can only fail if every worker panicked
+        self.job_sender.send((job, meshing_mode)).expect("every chunk worker has stopped");
+    }
+
+    // Returns a job that finished, if there is any.
+    pub fn try_receive(&self) -> Option<WorkerResult> {
+        match self.finished_receiver.try_recv() {
+            Ok(chunk) => Some(chunk),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => panic!("every chunk worker has stopped"),
+        }
+    }
+}
+
+// The workers stop by themselves once the pool gets dropped,
+// because receiving a job fails when the sender is gone.
+fn worker_loop(
+    job_receiver: Arc<Mutex<Receiver<(WorkerJob, MeshingMode)>>>,
+    finished_sender: Sender<WorkerResult>,
+    world_generator: Arc<WorldGenerator>,
+    block_registry: Arc<BlockRegistry>,
+) {
+    loop {
+        let (job, meshing_mode) = match job_receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        let finished = match job {
+            WorkerJob::Load(job, generation) => WorkerResult::Loaded(run_chunk_job(job, generation, meshing_mode, &world_generator, &block_registry)),
+            WorkerJob::Remesh(job) => WorkerResult::Remeshed(run_remesh_job(job, meshing_mode, world_generator.config(), &block_registry)),
+        };
+        if finished_sender.send(finished).is_err() {
+            return;
+        }
+    }
+}
+
+// Does the work of a job right away, on the thread that calls it.
+pub fn run_chunk_job(job: ChunkJob, generation: u64, meshing_mode: MeshingMode, world_generator: &WorldGenerator, block_registry: &BlockRegistry) -> FinishedChunk {
+    let (position, foreground_blocks, background_blocks) = match job {
+        ChunkJob::Generate(position) => {
+            let (foreground_blocks, background_blocks) = world_generator.generate_chunk(position);
+            (position, foreground_blocks, background_blocks)
+        }
+        ChunkJob::Mesh(position, foreground_blocks, background_blocks) => (position, foreground_blocks, background_blocks),
+    };
+
+    let light = compute_chunk_light(position, &foreground_blocks, block_registry, world_generator);
+    // The chunks around this one can't be seen from here, so the tiles
+    // around it are assumed to be untouched terrain without any shadows.
+    // The chunk manager meshes it again if it has neighbours once it's added.
+    let config = world_generator.config();
+    let chunk_width = config.chunk_width();
+    let neighbourhood = ChunkNeighbourhood::new(position, chunk_width, |pos| {
+        let Some(local) = position.local_of(pos, chunk_width) else {
+            return (assumed_sunlight(world_generator, pos.to_ivec2()), false);
+        };
+        let index = local.index(chunk_width);
+        (light.level(index), casts_ambient_occlusion(block_registry.get(foreground_blocks[index])))
+    });
+    let mut vertices = ChunkVertices::new();
+    build_chunk_vertices(&foreground_blocks, &background_blocks, &neighbourhood, block_registry, config, meshing_mode, &mut vertices);
+
+    return FinishedChunk {
+        position,
+        generation,
+        foreground_blocks,
+        background_blocks,
+        vertices,
+        light,
+    };
+}
+
+pub fn run_remesh_job(mut job: RemeshJob, meshing_mode: MeshingMode, config: &WorldConfig, block_registry: &BlockRegistry) -> RemeshedChunk {
+    build_chunk_vertices(&job.foreground_blocks, &job.background_blocks, &job.neighbourhood, block_registry, config, meshing_mode, &mut job.vertices);
+    return RemeshedChunk {
+        position: job.position,
+        revision: job.revision,
+        vertices: job.vertices,
+    };
+}
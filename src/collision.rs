@@ -1,6 +1,6 @@
 use macroquad::prelude::*;
 
-use crate::{chunk::{ChunkLayer, TILE_SIZE}, chunk_manager::ChunkManager};
+use crate::{block_registry::{BlockRegistry, AIR}, chunk::ChunkLayer, chunk_manager::ChunkManager, coordinates::BlockPos};
 
 pub struct RectangleCorners {
     pub bottom_left: Vec2,
@@ -67,66 +67,205 @@ impl Rectangle {
     }
 }
 
-// The function arguments are in world units, but the function itself only works with block units.
-// The returned position is also in block units.
-// If you wish to draw the intersection point or use it in any other context, you shall multiply it by "TILE_SIZE as f32".
-// 
-// There may be a way of making this raycasting algorithm to use world units instead,
-// however I don't understand how this algorithm works so I decided to not touch it.
-// - pvini07BR
-pub fn cast_ray_blocks(chunk_manager: &ChunkManager, layer: ChunkLayer, mut ray_origin: Vec2, mut ray_end: Vec2, maximum_ray_distance: f32) -> Option<Vec2> {
-    ray_origin /= TILE_SIZE as f32;
-    ray_end /= TILE_SIZE as f32;
+// Which blocks a raycast stops at, the other ones are passed through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RayFilter {
+    // Blocks that take up the whole tile, so flowers, torches and such are skipped
+    SOLID,
+    // Blocks the player collides with
+    COLLIDABLE,
+    // Every block that isn't air
+    ANY,
+    // Every block the player can point at, which is anything but air and fluids
+    TARGETABLE,
+}
 
-    let raydir = (ray_end - ray_origin).normalize();
+impl RayFilter {
+    pub fn hits(&self, block_id: usize, block_registry: &BlockRegistry) -> bool {
+        let block = block_registry.get(block_id);
+        return match self {
+            RayFilter::SOLID => block.solid,
+            RayFilter::COLLIDABLE => block.collidable,
+            RayFilter::ANY => block_id != AIR,
+            RayFilter::TARGETABLE => block_id != AIR && !block.fluid,
+        };
+    }
+}
 
-    let step_size = vec2(
-        f32::sqrt(1.0 + f32::powf(raydir.y / raydir.x, 2.0)),
-        f32::sqrt(1.0 + f32::powf(raydir.x / raydir.y, 2.0))
-    );
+// Where a raycast stopped. Everything is in world units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub block_position: BlockPos,
+    // Points out of the side of the block that was hit, towards where the ray came from.
+    // It's zero if the ray started inside of the block.
+    pub normal: IVec2,
+    pub point: Vec2,
+    // How far the point is from the origin of the ray
+    pub distance: f32,
+    pub block_id: usize,
+    pub layer: ChunkLayer,
+}
 
-    let mut block_pos: IVec2 = ray_origin.floor().as_ivec2();
-    let mut ray_length = Vec2::ZERO;
-    let mut vstep = IVec2::ZERO;
+// Casts a ray from ray_origin towards ray_end, both in world units, and returns the first block that
+// the filter hits. The ray doesn't stop at ray_end, only once it's longer than maximum_ray_distance.
+// Blocks that can be walked into, like plants and water, are skipped in the tile the ray starts in,
+// otherwise a player standing in tall grass could never point at anything else.
+pub fn cast_ray_blocks(
+    chunk_manager: &ChunkManager,
+    block_registry: &BlockRegistry,
+    layer: ChunkLayer,
+    filter: RayFilter,
+    ray_origin: Vec2,
+    ray_end: Vec2,
+    maximum_ray_distance: f32,
+) -> Option<RayHit> {
+    let tile_size = chunk_manager.config().tile_size();
+    let direction = (ray_end - ray_origin).try_normalize()?;
+    let origin_tile = (ray_origin / tile_size).floor().as_ivec2();
+    let (block_position, normal, distance) = cast_ray(ray_origin / tile_size, direction, maximum_ray_distance / tile_size, |pos| {
+        let block_id = chunk_manager.get_block(BlockPos::from_ivec2(pos), layer);
+        if pos == origin_tile && !block_registry.get(block_id).collidable {
+            return false;
+        }
+        return filter.hits(block_id, block_registry);
+    })?;
 
-    if raydir.x < 0.0 {
-        vstep.x = -1;
-        ray_length.x = (ray_origin.x - block_pos.x as f32) * step_size.x;
-    } else {
-        vstep.x = 1;
-        ray_length.x = ((block_pos.x + 1) as f32 - ray_origin.x) * step_size.x;
-    }
+    let block_position = BlockPos::from_ivec2(block_position);
+    return Some(RayHit {
+        block_position,
+        normal,
+        point: ray_origin + direction * distance * tile_size,
+        distance: distance * tile_size,
+        block_id: chunk_manager.get_block(block_position, layer),
+        layer,
+    });
+}
 
-    if raydir.y < 0.0 {
-        vstep.y = -1;
-        ray_length.y = (ray_origin.y - block_pos.y as f32) * step_size.y;
-    } else {
-        vstep.y = 1;
-        ray_length.y = ((block_pos.y + 1) as f32 - ray_origin.y) * step_size.y;
+// Walks through every tile the ray goes through, in order, until one of them is hit
+// (a DDA). Everything is in block units, and the direction has to be normalized.
+// Returns the block, the normal of the side that was hit and the distance to it.
+fn cast_ray(origin: Vec2, direction: Vec2, maximum_distance: f32, hits: impl Fn(IVec2) -> bool) -> Option<(IVec2, IVec2, f32)> {
+    let mut block_position = origin.floor().as_ivec2();
+    if hits(block_position) {
+        return Some((block_position, IVec2::ZERO, 0.0));
     }
 
-    let mut block_found: bool = false;
-    let mut distance: f32 = 0.0;
+    // How far the ray goes to cross a whole tile on each axis. A ray that is parallel to an
+    // axis never crosses the lines of that axis, so it's infinite instead of a division by zero.
+    let step_size = vec2(
+        if direction.x == 0.0 { f32::INFINITY } else { 1.0 / direction.x.abs() },
+        if direction.y == 0.0 { f32::INFINITY } else { 1.0 / direction.y.abs() },
+    );
+    let step = ivec2(if direction.x < 0.0 { -1 } else { 1 }, if direction.y < 0.0 { -1 } else { 1 });
+
+    // How far the ray goes until it crosses the next line on each axis
+    let distance_to_line = |origin: f32, block: i32, direction: f32, step_size: f32| {
+        if step_size.is_infinite() {
+            return f32::INFINITY;
+        }
+        let next_line = if direction < 0.0 { block as f32 } else { (block + 1) as f32 };
+        return (next_line - origin).abs() * step_size;
+    };
+    let mut ray_length = vec2(
+        distance_to_line(origin.x, block_position.x, direction.x, step_size.x),
+        distance_to_line(origin.y, block_position.y, direction.y, step_size.y),
+    );
 
-    while !block_found && distance < maximum_ray_distance {
-        if ray_length.x < ray_length.y {
-            block_pos.x += vstep.x;
-            distance = ray_length.x;
+    loop {
+        let (distance, normal) = if ray_length.x < ray_length.y {
+            block_position.x += step.x;
+            let distance = ray_length.x;
             ray_length.x += step_size.x;
+            (distance, ivec2(-step.x, 0))
         } else {
-            block_pos.y += vstep.y;
-            distance = ray_length.y;
+            block_position.y += step.y;
+            let distance = ray_length.y;
             ray_length.y += step_size.y;
+            (distance, ivec2(0, -step.y))
+        };
+
+        if distance > maximum_distance {
+            return None;
+        }
+        if hits(block_position) {
+            return Some((block_position, normal, distance));
         }
+    }
+}
+
+// Which sides of a box hit a tile while it was moving
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Contacts {
+    pub left: bool,
+    pub right: bool,
+    pub bottom: bool,
+    pub top: bool,
+}
+
+// Tiles that the box is only touching, but not inside of, have to be left out, and
+// float errors can put the box a tiny bit inside of a tile after it was pushed out of it.
+const TILE_EPSILON: f32 = 1e-4;
+
+// Moves the box by motion, in world units, stopping at the tiles where is_solid is true.
+// It moves along X first and then along Y, and every tile the box would go through on the
+// way is checked, so it can't go through thin walls no matter how fast it is.
+pub fn move_and_collide(aabb: &mut bounding_box::AxisAlignedRectangle, motion: Vec2, tile_size: f32, is_solid: impl Fn(IVec2) -> bool) -> Contacts {
+    let mut contacts = Contacts::default();
 
-        block_found = chunk_manager.get_block(block_pos, layer) > 0;
+    let (moved_x, hit_x) = sweep_axis(aabb, 0, motion.x, tile_size, &is_solid);
+    aabb.center_pos.x += moved_x;
+    contacts.right = hit_x && motion.x > 0.0;
+    contacts.left = hit_x && motion.x < 0.0;
+
+    let (moved_y, hit_y) = sweep_axis(aabb, 1, motion.y, tile_size, &is_solid);
+    aabb.center_pos.y += moved_y;
+    contacts.top = hit_y && motion.y > 0.0;
+    contacts.bottom = hit_y && motion.y < 0.0;
+
+    return contacts;
+}
+
+// How far the box can move on this axis (0 for X, 1 for Y), and if it hit a tile.
+fn sweep_axis(aabb: &bounding_box::AxisAlignedRectangle, axis: usize, motion: f32, tile_size: f32, is_solid: &impl Fn(IVec2) -> bool) -> (f32, bool) {
+    if motion == 0.0 {
+        return (0.0, false);
     }
+    let other_axis = 1 - axis;
+    let min = (aabb.center_pos - aabb.size * 0.5) / tile_size;
+    let max = (aabb.center_pos + aabb.size * 0.5) / tile_size;
 
-    if block_found {
-        return Some(ray_origin + raydir * distance);
+    // The rows (or columns) the box is inside of
+    let first_row = (min[other_axis] + TILE_EPSILON).floor() as i32;
+    let last_row = (max[other_axis] - TILE_EPSILON).ceil() as i32 - 1;
+    let tile_at = |column: i32, row: i32| {
+        let mut tile = IVec2::ZERO;
+        tile[axis] = column;
+        tile[other_axis] = row;
+        tile
+    };
+    let column_is_solid = |column: i32| (first_row..=last_row).any(|row| is_solid(tile_at(column, row)));
+
+    let step = motion / tile_size;
+    if step > 0.0 {
+        // From the first column in front of the box, to the last one it would get into
+        let first_column = (max[axis] - TILE_EPSILON).ceil() as i32;
+        let last_column = (max[axis] + step).ceil() as i32 - 1;
+        for column in first_column..=last_column {
+            if column_is_solid(column) {
+                return ((column as f32 - max[axis]) * tile_size, true);
+            }
+        }
     } else {
-        return None; 
+        let first_column = (min[axis] + TILE_EPSILON).floor() as i32 - 1;
+        let last_column = (min[axis] + step).floor() as i32;
+        for column in (last_column..=first_column).rev() {
+            if column_is_solid(column) {
+                return (((column + 1) as f32 - min[axis]) * tile_size, true);
+            }
+        }
     }
+
+    return (motion, false);
 }
 
 pub mod bounding_box {
@@ -148,10 +287,143 @@ pub mod bounding_box {
         pub fn intersects(&self, other: &AxisAlignedRectangle) -> bool {
             self.as_drectangle().intersects(other.as_drectangle())
         }
-        #[allow(dead_code)]
         pub fn debug_draw(&self, color: Color) {
             self.as_drectangle().draw(Color { a: 0.5, ..color });
             self.as_drectangle().draw_lines(2.0, color)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_hit_walls_in_every_direction() {
+        // A ring of walls 3 blocks away from the origin block
+        let walls = |pos: IVec2| pos.abs().max_element() == 3;
+        let origin = vec2(0.5, 0.5);
+
+        let directions = [
+            (vec2(1.0, 0.0), ivec2(3, 0), ivec2(-1, 0)),
+            (vec2(-1.0, 0.0), ivec2(-3, 0), ivec2(1, 0)),
+            (vec2(0.0, 1.0), ivec2(0, 3), ivec2(0, -1)),
+            (vec2(0.0, -1.0), ivec2(0, -3), ivec2(0, 1)),
+            // One for each octant
+            (vec2(2.0, 1.0), ivec2(3, 1), ivec2(-1, 0)),
+            (vec2(1.0, 2.0), ivec2(1, 3), ivec2(0, -1)),
+            (vec2(-1.0, 2.0), ivec2(-1, 3), ivec2(0, -1)),
+            (vec2(-2.0, 1.0), ivec2(-3, 1), ivec2(1, 0)),
+            (vec2(-2.0, -1.0), ivec2(-3, -1), ivec2(1, 0)),
+            (vec2(-1.0, -2.0), ivec2(-1, -3), ivec2(0, 1)),
+            (vec2(1.0, -2.0), ivec2(1, -3), ivec2(0, 1)),
+            (vec2(2.0, -1.0), ivec2(3, -1), ivec2(-1, 0)),
+        ];
+        for (direction, block, normal) in directions {
+            let direction = direction.normalize();
+            let (hit_block, hit_normal, distance) = cast_ray(origin, direction, 10.0, walls).unwrap();
+            assert_eq!((hit_block, hit_normal), (block, normal), "direction {}", direction);
+
+            // The point is on the side of the block that was hit
+            let point = origin + direction * distance;
+            let side = block.as_vec2() + Vec2::splat(0.5) + normal.as_vec2() * 0.5;
+            if normal.x != 0 {
+                assert!((point.x - side.x).abs() < 1e-4, "direction {}", direction);
+            } else {
+                assert!((point.y - side.y).abs() < 1e-4, "direction {}", direction);
+            }
+        }
+    }
+
+    #[test]
+    fn rays_stop_at_the_maximum_distance() {
+        let walls = |pos: IVec2| pos.x == 3;
+        assert_eq!(cast_ray(vec2(0.5, 0.5), Vec2::X, 2.4, walls), None);
+        assert_eq!(cast_ray(vec2(0.5, 0.5), Vec2::X, 2.5, walls), Some((ivec2(3, 0), ivec2(-1, 0), 2.5)));
+        // Starting inside of a block
+        assert_eq!(cast_ray(vec2(3.5, 0.5), Vec2::NEG_Y, 2.0, walls), Some((ivec2(3, 0), IVec2::ZERO, 0.0)));
+        // Going along the wall, right on the line between two tiles
+        assert_eq!(cast_ray(vec2(2.0, 0.0), Vec2::Y, 100.0, walls), None);
+    }
+
+    // The default tile size, and sizes where the tiles don't line up with whole pixels
+    const TILE_SIZES: [f32; 3] = [32.0, 16.0, 50.0];
+
+    fn block_box(center_in_blocks: Vec2, tile_size: f32) -> bounding_box::AxisAlignedRectangle {
+        return bounding_box::AxisAlignedRectangle {
+            center_pos: center_in_blocks * tile_size,
+            size: Vec2::splat(tile_size - 8.0),
+        };
+    }
+
+    #[test]
+    fn boxes_land_on_the_floor() {
+        let floor = |pos: IVec2| pos.y < 0;
+        for tile_size in TILE_SIZES {
+            let mut aabb = block_box(vec2(0.5, 2.0), tile_size);
+            let contacts = move_and_collide(&mut aabb, vec2(0.0, -100.0), tile_size, floor);
+            assert_eq!(contacts, Contacts { bottom: true, ..Default::default() });
+            assert!((aabb.as_drectangle().pos_bottom_left.y).abs() < 1e-3);
+
+            // Standing still on the floor is still touching it
+            let contacts = move_and_collide(&mut aabb, vec2(0.0, -1.0), tile_size, floor);
+            assert!(contacts.bottom);
+            assert!((aabb.as_drectangle().pos_bottom_left.y).abs() < 1e-3);
+
+            // Walking along the floor doesn't get stuck on the lines between the tiles
+            let contacts = move_and_collide(&mut aabb, vec2(200.0, -1.0), tile_size, floor);
+            assert_eq!(contacts, Contacts { bottom: true, ..Default::default() });
+            assert!((aabb.center_pos.x - (0.5 * tile_size + 200.0)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn boxes_slide_down_walls_instead_of_climbing_them() {
+        let wall = |pos: IVec2| pos.x == 2;
+        for tile_size in TILE_SIZES {
+            let mut aabb = block_box(vec2(1.3, 5.0), tile_size);
+            let contacts = move_and_collide(&mut aabb, vec2(20.0, -10.0), tile_size, wall);
+            assert_eq!(contacts, Contacts { right: true, ..Default::default() });
+            assert!((aabb.as_drectangle().corners().top_right.x - 2.0 * tile_size).abs() < 1e-3);
+            assert!((aabb.center_pos.y - (5.0 * tile_size - 10.0)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn fast_boxes_dont_go_through_thin_walls() {
+        let thin_wall = |pos: IVec2| pos.x == -20;
+        for tile_size in TILE_SIZES {
+            let mut aabb = block_box(vec2(0.5, 0.5), tile_size);
+            let contacts = move_and_collide(&mut aabb, vec2(-100.0 * tile_size, 0.0), tile_size, thin_wall);
+            assert!(contacts.left);
+            assert!((aabb.as_drectangle().pos_bottom_left.x - -19.0 * tile_size).abs() < 1e-3);
+
+            // But they fit through gaps that are as big as a tile
+            let ceiling_with_a_gap = |pos: IVec2| pos.y == 3 && pos.x != 0;
+            let mut aabb = block_box(vec2(0.5, 0.5), tile_size);
+            let contacts = move_and_collide(&mut aabb, vec2(0.0, 10.0 * tile_size), tile_size, ceiling_with_a_gap);
+            assert_eq!(contacts, Contacts::default());
+        }
+    }
+
+    #[test]
+    fn ray_filters() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let stone = registry.id_by_name("stone").unwrap();
+        let glass = registry.id_by_name("glass").unwrap();
+        let torch = registry.id_by_name("torch").unwrap();
+        let water = registry.id_by_name("water").unwrap();
+
+        for filter in [RayFilter::SOLID, RayFilter::COLLIDABLE, RayFilter::ANY, RayFilter::TARGETABLE] {
+            assert!(!filter.hits(AIR, &registry));
+            assert!(filter.hits(stone, &registry));
+            assert!(filter.hits(glass, &registry));
+        }
+        assert!(!RayFilter::SOLID.hits(torch, &registry));
+        assert!(!RayFilter::COLLIDABLE.hits(torch, &registry));
+        assert!(RayFilter::ANY.hits(torch, &registry));
+        assert!(RayFilter::TARGETABLE.hits(torch, &registry));
+        assert!(RayFilter::ANY.hits(water, &registry));
+        assert!(!RayFilter::TARGETABLE.hits(water, &registry));
+    }
+}
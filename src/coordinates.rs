@@ -0,0 +1,193 @@
+use std::{fmt::Display, ops::Add};
+
+use macroquad::prelude::*;
+
+// The three kinds of positions in the world, they all used to be plain IVec2s,
+// which made it way too easy to pass a block position where a chunk position was expected.
+// Everything is integer math, floats can't tell apart blocks this far from the
+// origin: at x = 2^24 + 1, x as f32 is rounded to 2^24.
+// The chunk width is picked per world (see WorldConfig), so it's passed to
+// everything that goes between blocks and chunks.
+
+// The position of a block in the world, in blocks. The vector inside is private,
+// going back and forth with IVec2 has to be done explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockPos(IVec2);
+
+// The position of a chunk in the world, in chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkPos(pub IVec2);
+
+// The position of a block inside its chunk, both coordinates are always less than the chunk width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocalPos(UVec2);
+
+impl BlockPos {
+    pub fn new(x: i32, y: i32) -> BlockPos {
+        return BlockPos(ivec2(x, y));
+    }
+
+    pub fn from_ivec2(position: IVec2) -> BlockPos {
+        return BlockPos(position);
+    }
+
+    pub fn to_ivec2(self) -> IVec2 {
+        return self.0;
+    }
+
+    pub fn x(self) -> i32 {
+        return self.0.x;
+    }
+
+    pub fn y(self) -> i32 {
+        return self.0.y;
+    }
+
+    // The block that contains this point in world units.
+    pub fn from_world(world_position: Vec2, tile_size: f32) -> BlockPos {
+        return BlockPos((world_position / tile_size).floor().as_ivec2());
+    }
+
+    pub fn chunk(self, chunk_width: usize) -> ChunkPos {
+        return ChunkPos(self.0.div_euclid(IVec2::splat(chunk_width as i32)));
+    }
+
+    pub fn local(self, chunk_width: usize) -> LocalPos {
+        return LocalPos(self.0.rem_euclid(IVec2::splat(chunk_width as i32)).as_uvec2());
+    }
+
+    pub fn split(self, chunk_width: usize) -> (ChunkPos, LocalPos) {
+        return (self.chunk(chunk_width), self.local(chunk_width));
+    }
+}
+
+impl ChunkPos {
+    pub fn new(x: i32, y: i32) -> ChunkPos {
+        return ChunkPos(ivec2(x, y));
+    }
+
+    // The bottom left block of the chunk.
+    pub fn origin(self, chunk_width: usize) -> BlockPos {
+        return BlockPos(self.0 * chunk_width as i32);
+    }
+
+    pub fn block(self, local: LocalPos, chunk_width: usize) -> BlockPos {
+        return BlockPos(self.origin(chunk_width).0 + local.0.as_ivec2());
+    }
+
+    // Where this block is in the chunk, None if it's in another chunk.
+    pub fn local_of(self, block: BlockPos, chunk_width: usize) -> Option<LocalPos> {
+        let (chunk, local) = block.split(chunk_width);
+        if chunk != self {
+            return None;
+        }
+        return Some(local);
+    }
+}
+
+impl LocalPos {
+    pub fn new(x: u32, y: u32, chunk_width: usize) -> LocalPos {
+        assert!(
+            x < chunk_width as u32 && y < chunk_width as u32,
+            "({}, {}) is outside of a chunk",
+            x,
+            y
+        );
+        return LocalPos(uvec2(x, y));
+    }
+
+    pub fn from_index(index: usize, chunk_width: usize) -> LocalPos {
+        return LocalPos::new((index % chunk_width) as u32, (index / chunk_width) as u32, chunk_width);
+    }
+
+    pub fn x(self) -> u32 {
+        return self.0.x;
+    }
+
+    pub fn y(self) -> u32 {
+        return self.0.y;
+    }
+
+    // Where this block is in the block arrays of a chunk.
+    pub fn index(self, chunk_width: usize) -> usize {
+        return self.0.x as usize + self.0.y as usize * chunk_width;
+    }
+}
+
+impl Add<IVec2> for BlockPos {
+    type Output = BlockPos;
+
+    fn add(self, offset: IVec2) -> BlockPos {
+        return BlockPos(self.0 + offset);
+    }
+}
+
+impl Add<IVec2> for ChunkPos {
+    type Output = ChunkPos;
+
+    fn add(self, offset: IVec2) -> ChunkPos {
+        return ChunkPos(self.0 + offset);
+    }
+}
+
+impl Display for BlockPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return self.0.fmt(f);
+    }
+}
+
+impl Display for ChunkPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return self.0.fmt(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn negative_blocks_belong_to_negative_chunks() {
+        for width in [4, 16, 33] {
+            let w = width as i32;
+            assert_eq!(
+                BlockPos::new(-1, 0).split(width),
+                (ChunkPos::new(-1, 0), LocalPos::new(w as u32 - 1, 0, width))
+            );
+            assert_eq!(BlockPos::new(-w, w).chunk(width), ChunkPos::new(-1, 1));
+            assert_eq!(BlockPos::new(-w - 1, 0).chunk(width), ChunkPos::new(-2, 0));
+        }
+    }
+
+    #[test]
+    fn blocks_far_away_keep_their_chunk() {
+        // Floats would put both of these in the same chunk
+        let block = BlockPos::new((1 << 24) + 16 + 1, 0);
+        assert_eq!(block.chunk(16), ChunkPos::new((1 << 24) / 16 + 1, 0));
+        assert_eq!(block.local(16), LocalPos::new(1, 0, 16));
+
+        let (chunk, local) = BlockPos::new(i32::MIN, i32::MAX).split(16);
+        assert_eq!(chunk.block(local, 16), BlockPos::new(i32::MIN, i32::MAX));
+    }
+
+    proptest! {
+        #[test]
+        fn block_positions_survive_the_round_trip(x in any::<i32>(), y in any::<i32>(), width in 1..=128usize) {
+            let block = BlockPos::new(x, y);
+            let (chunk, local) = block.split(width);
+            prop_assert_eq!(chunk.block(local, width), block);
+            prop_assert_eq!(chunk.local_of(block, width), Some(local));
+            prop_assert_eq!(LocalPos::from_index(local.index(width), width), local);
+        }
+
+        #[test]
+        fn chunks_contain_their_blocks(x in any::<i32>(), y in any::<i32>(), width in 1..=128usize, index in any::<prop::sample::Index>()) {
+            let index = index.index(width * width);
+            let chunk = ChunkPos::new(x.div_euclid(width as i32), y.div_euclid(width as i32));
+            let block = chunk.block(LocalPos::from_index(index, width), width);
+            prop_assert_eq!(block.chunk(width), chunk);
+            prop_assert_eq!(block.local(width).index(width), index);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use macroquad::prelude::*;
+
+use crate::block_registry::{BlockRegistry, AIR};
+
+pub const HOTBAR_SLOTS: usize = 9;
+
+// The textures are tiny, so everything is drawn bigger than them
+const UI_SCALE: f32 = 1.5;
+const SLOT_SIZE: f32 = 42.0 * UI_SCALE;
+// How far the block icon is from the border of the slot
+const SLOT_PADDING: f32 = 9.0 * UI_SCALE;
+// The border of panel.png that doesn't get stretched
+const PANEL_BORDER: f32 = 6.0;
+const PANEL_PADDING: f32 = 12.0 * UI_SCALE;
+const INVENTORY_COLUMNS: usize = HOTBAR_SLOTS;
+const HOTBAR_BOTTOM_MARGIN: f32 = 8.0;
+
+// The blocks the player can place, only one of them is selected at a time.
+pub struct Hotbar {
+    slots: [usize; HOTBAR_SLOTS],
+    selected_slot: usize,
+}
+
+impl Hotbar {
+    // Filled with the first blocks of the registry
+    pub fn new(block_registry: &BlockRegistry) -> Hotbar {
+        let mut slots = [AIR; HOTBAR_SLOTS];
+        for (slot, id) in slots.iter_mut().zip(1..block_registry.len()) {
+            *slot = id;
+        }
+        return Hotbar { slots, selected_slot: 0 };
+    }
+
+    pub fn selected_block(&self) -> usize {
+        return self.slots[self.selected_slot];
+    }
+
+    pub fn selected_slot(&self) -> usize {
+        return self.selected_slot;
+    }
+
+    pub fn select(&mut self, slot: usize) {
+        if slot < HOTBAR_SLOTS {
+            self.selected_slot = slot;
+        }
+    }
+
+    // Moves the selection by this many slots, wrapping around both ends.
+    pub fn scroll(&mut self, slots: i32) {
+        self.selected_slot = (self.selected_slot as i32 + slots).rem_euclid(HOTBAR_SLOTS as i32) as usize;
+    }
+
+    pub fn set_selected_block(&mut self, block_id: usize) {
+        self.slots[self.selected_slot] = block_id;
+    }
+
+    pub fn input(&mut self) {
+        const NUMBER_KEYS: [KeyCode; HOTBAR_SLOTS] = [
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Key4,
+            KeyCode::Key5,
+            KeyCode::Key6,
+            KeyCode::Key7,
+            KeyCode::Key8,
+            KeyCode::Key9,
+        ];
+        for (slot, key) in NUMBER_KEYS.iter().enumerate() {
+            if is_key_pressed(*key) {
+                self.select(slot);
+            }
+        }
+
+        // Control + wheel is used for zooming
+        if !is_key_down(KeyCode::LeftControl) {
+            if mouse_wheel().1 > 0.0 {
+                self.scroll(-1);
+            } else if mouse_wheel().1 < 0.0 {
+                self.scroll(1);
+            }
+        }
+    }
+}
+
+// The hotbar at the bottom of the screen, and the panel with
+// every block in the registry that opens on top of the world.
+pub struct InventoryUi {
+    item_slot_texture: Texture2D,
+    item_slot_selected_texture: Texture2D,
+    panel_texture: Texture2D,
+    open: bool,
+}
+
+impl InventoryUi {
+    pub async fn load() -> InventoryUi {
+        let load = |texture: Texture2D| {
+            texture.set_filter(FilterMode::Nearest);
+            texture
+        };
+        return InventoryUi {
+            item_slot_texture: load(load_texture("assets/textures/itemSlot.png").await.unwrap()),
+            item_slot_selected_texture: load(load_texture("assets/textures/itemSlot_selected.png").await.unwrap()),
+            panel_texture: load(load_texture("assets/textures/panel.png").await.unwrap()),
+            open: false,
+        };
+    }
+
+    // Returns true if the mouse is on top of the UI, so clicks shouldn't go to the world.
+    pub fn input(&mut self, hotbar: &mut Hotbar, block_registry: &BlockRegistry) -> bool {
+        if is_key_pressed(KeyCode::E) {
+            self.open = !self.open;
+        }
+        if self.open && is_key_pressed(KeyCode::Escape) {
+            self.open = false;
+        }
+
+        let mouse = Vec2::from(mouse_position());
+        let clicked = is_mouse_button_pressed(MouseButton::Left);
+
+        if let Some(slot) = (0..HOTBAR_SLOTS).find(|slot| hotbar_slot_rect(*slot).contains(mouse)) {
+            if clicked {
+                hotbar.select(slot);
+            }
+            return true;
+        }
+
+        if !self.open {
+            return false;
+        }
+        let block_count = block_registry.len() - 1;
+        if let Some(slot) = (0..block_count).find(|slot| inventory_slot_rect(*slot, block_count).contains(mouse)) {
+            if clicked {
+                // Air is not in the inventory
+                hotbar.set_selected_block(slot + 1);
+            }
+        }
+        // The whole panel is in the way of the world
+        return inventory_panel_rect(block_count).contains(mouse);
+    }
+
+    pub fn draw(&self, hotbar: &Hotbar, block_registry: &BlockRegistry, blocks_atlas_texture: &Texture2D) {
+        for (slot, block_id) in hotbar.slots.iter().enumerate() {
+            let texture = match slot == hotbar.selected_slot() {
+                true => &self.item_slot_selected_texture,
+                false => &self.item_slot_texture,
+            };
+            self.draw_slot(hotbar_slot_rect(slot), texture, *block_id, block_registry, blocks_atlas_texture);
+        }
+
+        if !self.open {
+            return;
+        }
+
+        let block_count = block_registry.len() - 1;
+        self.draw_panel(inventory_panel_rect(block_count));
+        let mouse = Vec2::from(mouse_position());
+        let mut hovered_block: Option<usize> = None;
+        for slot in 0..block_count {
+            let rect = inventory_slot_rect(slot, block_count);
+            let block_id = slot + 1;
+            let texture = match block_id == hotbar.selected_block() {
+                true => &self.item_slot_selected_texture,
+                false => &self.item_slot_texture,
+            };
+            self.draw_slot(rect, texture, block_id, block_registry, blocks_atlas_texture);
+            if rect.contains(mouse) {
+                hovered_block = Some(block_id);
+            }
+        }
+
+        if let Some(block_id) = hovered_block {
+            let name = &block_registry.get(block_id).name;
+            let size = measure_text(name, None, 32, 1.0);
+            draw_rectangle(mouse.x + 16.0, mouse.y - size.height - 8.0, size.width + 8.0, size.height + 8.0, color_u8!(0.0, 0.0, 0.0, 192.0));
+            draw_text(name, mouse.x + 20.0, mouse.y - 8.0 - (size.height - size.offset_y), 32.0, WHITE);
+        }
+    }
+
+    fn draw_slot(&self, rect: Rect, texture: &Texture2D, block_id: usize, block_registry: &BlockRegistry, blocks_atlas_texture: &Texture2D) {
+        draw_texture_ex(
+            texture,
+            rect.x,
+            rect.y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(rect.size()),
+                ..Default::default()
+            },
+        );
+
+        let Some(atlas_index) = block_registry.get(block_id).atlas_index else {
+            return;
+        };
+        let tile_size = blocks_atlas_texture.width() / block_registry.atlas_columns() as f32;
+        draw_texture_ex(
+            blocks_atlas_texture,
+            rect.x + SLOT_PADDING,
+            rect.y + SLOT_PADDING,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(rect.size() - Vec2::splat(SLOT_PADDING * 2.0)),
+                source: Some(Rect::new(atlas_index as f32 * tile_size, 0.0, tile_size, blocks_atlas_texture.height())),
+                ..Default::default()
+            },
+        );
+    }
+
+    // The panel texture is split in 9 parts, so the corners keep their size no matter how big the panel is.
+    fn draw_panel(&self, rect: Rect) {
+        let texture_size = self.panel_texture.size();
+        let border = PANEL_BORDER * UI_SCALE;
+        // Where each column and row starts and how big it is, in the texture and on the screen
+        let source = [
+            (0.0, PANEL_BORDER),
+            (PANEL_BORDER, texture_size.x - PANEL_BORDER * 2.0),
+            (texture_size.x - PANEL_BORDER, PANEL_BORDER),
+        ];
+        let dest_x = [(rect.x, border), (rect.x + border, rect.w - border * 2.0), (rect.right() - border, border)];
+        let dest_y = [(rect.y, border), (rect.y + border, rect.h - border * 2.0), (rect.bottom() - border, border)];
+
+        for (row, (y, height)) in dest_y.iter().enumerate() {
+            for (column, (x, width)) in dest_x.iter().enumerate() {
+                draw_texture_ex(
+                    &self.panel_texture,
+                    *x,
+                    *y,
+                    WHITE,
+                    DrawTextureParams {
+                        dest_size: Some(vec2(*width, *height)),
+                        source: Some(Rect::new(source[column].0, source[row].0, source[column].1, source[row].1)),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+}
+
+fn hotbar_slot_rect(slot: usize) -> Rect {
+    let hotbar_width = SLOT_SIZE * HOTBAR_SLOTS as f32;
+    return Rect::new(
+        (screen_width() - hotbar_width) / 2.0 + slot as f32 * SLOT_SIZE,
+        screen_height() - SLOT_SIZE - HOTBAR_BOTTOM_MARGIN,
+        SLOT_SIZE,
+        SLOT_SIZE,
+    );
+}
+
+fn inventory_panel_rect(block_count: usize) -> Rect {
+    let rows = block_count.div_ceil(INVENTORY_COLUMNS).max(1);
+    let size = vec2(INVENTORY_COLUMNS as f32, rows as f32) * SLOT_SIZE + Vec2::splat(PANEL_PADDING * 2.0);
+    return Rect::new((screen_width() - size.x) / 2.0, (screen_height() - size.y) / 2.0, size.x, size.y);
+}
+
+fn inventory_slot_rect(slot: usize, block_count: usize) -> Rect {
+    let panel = inventory_panel_rect(block_count);
+    return Rect::new(
+        panel.x + PANEL_PADDING + (slot % INVENTORY_COLUMNS) as f32 * SLOT_SIZE,
+        panel.y + PANEL_PADDING + (slot / INVENTORY_COLUMNS) as f32 * SLOT_SIZE,
+        SLOT_SIZE,
+        SLOT_SIZE,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hotbar_selection_wraps_around() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let mut hotbar = Hotbar::new(&registry);
+        assert_eq!(hotbar.selected_block(), 1);
+
+        hotbar.scroll(-1);
+        assert_eq!(hotbar.selected_slot(), HOTBAR_SLOTS - 1);
+        assert_eq!(hotbar.selected_block(), HOTBAR_SLOTS);
+        hotbar.scroll(2);
+        assert_eq!(hotbar.selected_slot(), 1);
+
+        hotbar.select(HOTBAR_SLOTS);
+        assert_eq!(hotbar.selected_slot(), 1);
+        hotbar.set_selected_block(7);
+        assert_eq!(hotbar.selected_block(), 7);
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{chunk::ChunkLayer, player::Player, world_config::WorldConfig};
+
+const LEVEL_FILE_NAME: &str = "level.ron";
+
+// Everything about a world that isn't stored inside its chunks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    pub seed: u64,
+    // Where the player starts in a new world, in world units
+    pub spawn_point: [f32; 2],
+    pub player: PlayerState,
+    pub current_block_layer: ChunkLayer,
+    pub zoom: f32,
+    // In seconds
+    pub play_time: f64,
+    // Levels saved before the sizes could be changed were made with the default ones
+    #[serde(default)]
+    pub world_config: WorldConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    pub noclip: bool,
+    pub sprite_rotation: f32,
+}
+
+impl Level {
+    // The player starts at the spawn point.
+    pub fn new(seed: u64, spawn_point: Vec2, world_config: WorldConfig) -> Level {
+        Level {
+            seed,
+            spawn_point: spawn_point.to_array(),
+            player: PlayerState {
+                position: spawn_point.to_array(),
+                velocity: [0.0, 0.0],
+                noclip: false,
+                sprite_rotation: 0.0,
+            },
+            current_block_layer: ChunkLayer::FOREGROUND,
+            zoom: 1.0,
+            play_time: 0.0,
+            world_config,
+        }
+    }
+
+    // Returns None if the world doesn't have a level file yet.
+    pub fn load(world_directory: &Path) -> io::Result<Option<Level>> {
+        let contents = match fs::read_to_string(level_path(world_directory)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let level: Level = ron::from_str(&contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        level.world_config.validate()?;
+        return Ok(Some(level));
+    }
+
+    pub fn save(&self, world_directory: &Path) -> io::Result<()> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+        fs::create_dir_all(world_directory)?;
+        let path = level_path(world_directory);
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, contents)?;
+        return fs::rename(temporary_path, path);
+    }
+
+    pub fn create_player(&self) -> Player {
+        let mut player = Player::new(Vec2::from_array(self.player.position), self.world_config.tile_size());
+        player.velocity = Vec2::from_array(self.player.velocity);
+        player.noclip = self.player.noclip;
+        player.sprite_rotation = self.player.sprite_rotation;
+        return player;
+    }
+
+    pub fn store_player(&mut self, player: &Player) {
+        self.player = PlayerState {
+            position: player.get_position().to_array(),
+            velocity: player.velocity.to_array(),
+            noclip: player.noclip,
+            sprite_rotation: player.sprite_rotation,
+        };
+    }
+}
+
+fn level_path(world_directory: &Path) -> PathBuf {
+    return world_directory.join(LEVEL_FILE_NAME);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        assert_eq!(Level::load(directory.path()).unwrap(), None);
+
+        let mut level = Level::new(1234567890123, vec2(16.0, 48.0), WorldConfig::new(24, 20).unwrap());
+        assert_eq!(level.player.position, level.spawn_point);
+        level.player.position = [-300.5, 1024.25];
+        level.player.velocity = [0.0, -530.0];
+        level.player.noclip = true;
+        level.player.sprite_rotation = -3.5;
+        level.current_block_layer = ChunkLayer::BACKGROUND;
+        level.zoom = 0.35;
+        level.play_time = 3725.5;
+        level.save(directory.path()).unwrap();
+
+        assert_eq!(Level::load(directory.path()).unwrap(), Some(level));
+    }
+
+    #[test]
+    fn old_levels_get_the_default_sizes() {
+        let directory = tempfile::tempdir().unwrap();
+        let level = Level::new(5, Vec2::ZERO, WorldConfig::default());
+        let contents = ron::ser::to_string(&level).unwrap().replace(",world_config:(chunk_width:16,tile_size:32)", "");
+        assert!(!contents.contains("world_config"));
+        fs::write(level_path(directory.path()), contents).unwrap();
+        assert_eq!(Level::load(directory.path()).unwrap(), Some(level));
+
+        // Chunks that are too big for the meshes are refused
+        let contents = ron::ser::to_string(&Level::new(5, Vec2::ZERO, WorldConfig::default())).unwrap().replace("chunk_width:16", "chunk_width:300");
+        fs::write(level_path(directory.path()), contents).unwrap();
+        assert!(Level::load(directory.path()).is_err());
+    }
+}
@@ -0,0 +1,25 @@
+// The code is written with explicit returns, so clippy shouldn't ask to drop them
+#![allow(clippy::needless_return)]
+
+// Everything about the world works without a window, only the chunk
+// renderer and the draw and input functions need one.
+pub mod biome;
+pub mod block_edit;
+pub mod block_registry;
+pub mod block_storage;
+pub mod chunk;
+pub mod chunk_manager;
+pub mod chunk_mesh;
+pub mod chunk_renderer;
+pub mod chunk_workers;
+pub mod collision;
+pub mod coordinates;
+pub mod inventory;
+pub mod level;
+pub mod lighting;
+pub mod placement;
+pub mod player;
+pub mod region;
+pub mod world_config;
+pub mod world_generator;
+pub mod world_save;
@@ -0,0 +1,571 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use macroquad::prelude::*;
+
+use crate::{
+    block_registry::{BlockProperties, BlockRegistry},
+    chunk::{Chunk, ChunkLayer},
+    coordinates::{BlockPos, ChunkPos},
+    world_generator::WorldGenerator,
+};
+
+pub const MAX_LIGHT: u8 = 15;
+// Light fades much faster inside blocks that aren't transparent, so the
+// surface of the ground is still lit but it gets dark quickly below it.
+const OPAQUE_ATTENUATION: u8 = 4;
+
+// How bright every light level looks, from 0 to MAX_LIGHT
+const LIGHT_CURVE: [f32; MAX_LIGHT as usize + 1] = [
+    0.04, 0.06, 0.08, 0.10, 0.13, 0.16, 0.20, 0.25, 0.31, 0.38, 0.46, 0.55, 0.65, 0.76, 0.88, 1.0,
+];
+
+const NEIGHBOURS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LightChannel {
+    // Light coming from the sky, it goes straight down without fading
+    SUN,
+    // Light coming from blocks like torches
+    BLOCK,
+}
+
+const CHANNELS: [LightChannel; 2] = [LightChannel::SUN, LightChannel::BLOCK];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkLight {
+    sunlight: Box<[u8]>,
+    block_light: Box<[u8]>,
+}
+
+impl ChunkLight {
+    // A dark chunk, chunk_area is the number of tiles in it.
+    pub fn new(chunk_area: usize) -> ChunkLight {
+        ChunkLight {
+            sunlight: vec![0; chunk_area].into_boxed_slice(),
+            block_light: vec![0; chunk_area].into_boxed_slice(),
+        }
+    }
+
+    pub fn get(&self, index: usize, channel: LightChannel) -> u8 {
+        return match channel {
+            LightChannel::SUN => self.sunlight[index],
+            LightChannel::BLOCK => self.block_light[index],
+        };
+    }
+
+    pub fn set(&mut self, index: usize, channel: LightChannel, level: u8) {
+        match channel {
+            LightChannel::SUN => self.sunlight[index] = level,
+            LightChannel::BLOCK => self.block_light[index] = level,
+        }
+    }
+
+    // The light level that is actually seen on this tile
+    pub fn level(&self, index: usize) -> u8 {
+        return self.sunlight[index].max(self.block_light[index]);
+    }
+}
+
+pub fn light_brightness(level: u8) -> f32 {
+    return LIGHT_CURVE[level.min(MAX_LIGHT) as usize];
+}
+
+// How much light is lost when it enters a tile with this foreground block.
+pub fn light_attenuation(block: &BlockProperties) -> u8 {
+    return match block.transparent {
+        true => 1,
+        false => OPAQUE_ATTENUATION,
+    };
+}
+
+// Anything light can be propagated through. Light is only ever written to loaded
+// blocks, the light of the other ones is whatever the world would have there.
+pub trait LightWorld {
+    fn is_loaded(&self, pos: IVec2) -> bool;
+    fn get_light(&self, pos: IVec2, channel: LightChannel) -> u8;
+    fn set_light(&mut self, pos: IVec2, channel: LightChannel, level: u8);
+    fn attenuation(&self, pos: IVec2) -> u8;
+    fn emission(&self, pos: IVec2) -> u8;
+}
+
+// The light a tile gets from its neighbour, given the light of the neighbour.
+fn light_from_neighbour(world: &impl LightWorld, channel: LightChannel, source_level: u8, direction: IVec2, pos: IVec2) -> u8 {
+    let attenuation = world.attenuation(pos);
+    if channel == LightChannel::SUN && direction == IVec2::NEG_Y && source_level == MAX_LIGHT && attenuation == 1 {
+        return MAX_LIGHT;
+    }
+    return source_level.saturating_sub(attenuation);
+}
+
+// Spreads the light of every queued tile to its neighbours, until nothing gets brighter.
+pub fn propagate_light(world: &mut impl LightWorld, queue: &mut VecDeque<(IVec2, LightChannel)>) {
+    while let Some((pos, channel)) = queue.pop_front() {
+        let level = world.get_light(pos, channel);
+        if level == 0 {
+            continue;
+        }
+        for direction in NEIGHBOURS {
+            let neighbour = pos + direction;
+            if !world.is_loaded(neighbour) {
+                continue;
+            }
+            let new_level = light_from_neighbour(world, channel, level, direction, neighbour);
+            if new_level > world.get_light(neighbour, channel) {
+                world.set_light(neighbour, channel, new_level);
+                queue.push_back((neighbour, channel));
+            }
+        }
+    }
+}
+
+// Darkens the tile and everything that was lit through it. The tiles around the darkened
+// area that still have light are added to the queue, so propagate_light can fill it back in.
+pub fn remove_light(world: &mut impl LightWorld, pos: IVec2, channel: LightChannel, refill: &mut VecDeque<(IVec2, LightChannel)>) {
+    let level = world.get_light(pos, channel);
+    if level == 0 || !world.is_loaded(pos) {
+        return;
+    }
+    world.set_light(pos, channel, 0);
+
+    let mut removal: VecDeque<(IVec2, u8)> = VecDeque::from([(pos, level)]);
+    while let Some((pos, level)) = removal.pop_front() {
+        for direction in NEIGHBOURS {
+            let neighbour = pos + direction;
+            let neighbour_level = world.get_light(neighbour, channel);
+            if neighbour_level == 0 {
+                continue;
+            }
+
+            let was_lit_by_pos = neighbour_level < level
+                || (channel == LightChannel::SUN && direction == IVec2::NEG_Y && level == MAX_LIGHT && neighbour_level == MAX_LIGHT);
+            if was_lit_by_pos && world.is_loaded(neighbour) {
+                world.set_light(neighbour, channel, 0);
+                removal.push_back((neighbour, neighbour_level));
+                // Other torches still shine on their own, the light
+                // around them comes back when the queue is propagated
+                let emission = world.emission(neighbour);
+                if channel == LightChannel::BLOCK && emission > 0 {
+                    world.set_light(neighbour, channel, emission);
+                    refill.push_back((neighbour, channel));
+                }
+            } else {
+                refill.push_back((neighbour, channel));
+            }
+        }
+    }
+}
+
+// Has to be called after the foreground block of a loaded tile changes.
+pub fn update_light_at(world: &mut impl LightWorld, pos: IVec2) {
+    let mut refill: VecDeque<(IVec2, LightChannel)> = VecDeque::new();
+    for channel in CHANNELS {
+        remove_light(world, pos, channel, &mut refill);
+        // The light around the tile has to be able to come back into it,
+        // in case the new block lets more light through.
+        for direction in NEIGHBOURS {
+            refill.push_back((pos + direction, channel));
+        }
+    }
+
+    let emission = world.emission(pos);
+    if emission > world.get_light(pos, LightChannel::BLOCK) {
+        world.set_light(pos, LightChannel::BLOCK, emission);
+        refill.push_back((pos, LightChannel::BLOCK));
+    }
+
+    propagate_light(world, &mut refill);
+}
+
+// Lights a chunk that doesn't have any light yet. Only
+// the tiles around it are used, the chunk itself can be dark.
+pub fn light_chunk(world: &mut impl LightWorld, chunk_position: ChunkPos, chunk_width: usize) {
+    let mut queue: VecDeque<(IVec2, LightChannel)> = VecDeque::new();
+    let origin = chunk_position.origin(chunk_width).to_ivec2();
+    let width = chunk_width as i32;
+
+    // Sunlight goes straight down from the sky until it hits something
+    for x in 0..width {
+        let above = origin + ivec2(x, width);
+        if world.get_light(above, LightChannel::SUN) != MAX_LIGHT {
+            continue;
+        }
+        for y in (0..width).rev() {
+            let pos = origin + ivec2(x, y);
+            if world.attenuation(pos) != 1 {
+                break;
+            }
+            world.set_light(pos, LightChannel::SUN, MAX_LIGHT);
+            queue.push_back((pos, LightChannel::SUN));
+        }
+    }
+
+    for y in 0..width {
+        for x in 0..width {
+            let pos = origin + ivec2(x, y);
+            let emission = world.emission(pos);
+            if emission > world.get_light(pos, LightChannel::BLOCK) {
+                world.set_light(pos, LightChannel::BLOCK, emission);
+                queue.push_back((pos, LightChannel::BLOCK));
+            }
+        }
+    }
+
+    for (outside, _) in chunk_border(chunk_position, chunk_width) {
+        for channel in CHANNELS {
+            queue.push_back((outside, channel));
+        }
+    }
+
+    propagate_light(world, &mut queue);
+}
+
+// Every tile on the border of the chunk, paired with the tile outside of the chunk next to it.
+fn chunk_border(chunk_position: ChunkPos, chunk_width: usize) -> Vec<(IVec2, IVec2)> {
+    let origin = chunk_position.origin(chunk_width).to_ivec2();
+    let width = chunk_width as i32;
+    let mut border = Vec::with_capacity(chunk_width * 4);
+    for i in 0..width {
+        border.push((origin + ivec2(i, -1), origin + ivec2(i, 0)));
+        border.push((origin + ivec2(i, width), origin + ivec2(i, width - 1)));
+        border.push((origin + ivec2(-1, i), origin + ivec2(0, i)));
+        border.push((origin + ivec2(width, i), origin + ivec2(width - 1, i)));
+    }
+    return border;
+}
+
+// The sunlight a tile would have if nothing was built in the world:
+// everything above the terrain is lit by the sky.
+pub fn assumed_sunlight(world_generator: &WorldGenerator, pos: IVec2) -> u8 {
+    return match pos.y > world_generator.surface_height(pos.x) {
+        true => MAX_LIGHT,
+        false => 0,
+    };
+}
+
+// A single chunk on its own, used to light chunks on the chunk workers.
+pub struct SingleChunkLight<'a> {
+    pub position: ChunkPos,
+    pub foreground_blocks: &'a [usize],
+    pub block_registry: &'a BlockRegistry,
+    pub world_generator: &'a WorldGenerator,
+    pub light: ChunkLight,
+}
+
+impl SingleChunkLight<'_> {
+    fn local_index(&self, pos: IVec2) -> Option<usize> {
+        let chunk_width = self.world_generator.config().chunk_width();
+        return self.position.local_of(BlockPos::from_ivec2(pos), chunk_width).map(|local| local.index(chunk_width));
+    }
+}
+
+impl LightWorld for SingleChunkLight<'_> {
+    fn is_loaded(&self, pos: IVec2) -> bool {
+        return self.local_index(pos).is_some();
+    }
+
+    fn get_light(&self, pos: IVec2, channel: LightChannel) -> u8 {
+        return match (self.local_index(pos), channel) {
+            (Some(index), _) => self.light.get(index, channel),
+            (None, LightChannel::SUN) => assumed_sunlight(self.world_generator, pos),
+            (None, LightChannel::BLOCK) => 0,
+        };
+    }
+
+    fn set_light(&mut self, pos: IVec2, channel: LightChannel, level: u8) {
+        if let Some(index) = self.local_index(pos) {
+            self.light.set(index, channel, level);
+        }
+    }
+
+    fn attenuation(&self, pos: IVec2) -> u8 {
+        return match self.local_index(pos) {
+            Some(index) => light_attenuation(self.block_registry.get(self.foreground_blocks[index])),
+            None => 1,
+        };
+    }
+
+    fn emission(&self, pos: IVec2) -> u8 {
+        return match self.local_index(pos) {
+            Some(index) => self.block_registry.get(self.foreground_blocks[index]).light_emission,
+            None => 0,
+        };
+    }
+}
+
+// Lights a chunk as if it was alone in the world, assuming the chunks around it were never edited.
+pub fn compute_chunk_light(
+    position: ChunkPos,
+    foreground_blocks: &[usize],
+    block_registry: &BlockRegistry,
+    world_generator: &WorldGenerator,
+) -> ChunkLight {
+    let config = world_generator.config();
+    let mut world = SingleChunkLight {
+        position,
+        foreground_blocks,
+        block_registry,
+        world_generator,
+        light: ChunkLight::new(config.chunk_area()),
+    };
+    light_chunk(&mut world, position, config.chunk_width());
+    return world.light;
+}
+
+// Every chunk that is loaded, with the chunk width of the world generator. The chunks that get their light changed are added to dirty_chunks.
+pub struct LoadedChunksLight<'a> {
+    pub chunks: &'a mut HashMap<ChunkPos, Chunk>,
+    pub block_registry: &'a BlockRegistry,
+    pub world_generator: &'a WorldGenerator,
+    pub dirty_chunks: &'a mut HashSet<ChunkPos>,
+}
+
+impl LoadedChunksLight<'_> {
+    fn locate(&self, pos: IVec2) -> (ChunkPos, usize) {
+        let chunk_width = self.world_generator.config().chunk_width();
+        let (chunk_position, local) = BlockPos::from_ivec2(pos).split(chunk_width);
+        return (chunk_position, local.index(chunk_width));
+    }
+
+    fn assumed_light(&self, pos: IVec2, channel: LightChannel) -> u8 {
+        return match channel {
+            LightChannel::SUN => assumed_sunlight(self.world_generator, pos),
+            LightChannel::BLOCK => 0,
+        };
+    }
+
+    // A chunk that was just loaded got lit by compute_chunk_light, which guessed the light
+    // of the chunks around it, and the chunks around it did the same guess about it.
+    // This fixes the light on both sides of the border now that both of them are loaded.
+    pub fn stitch_chunk(&mut self, chunk_position: ChunkPos) {
+        let mut refill: VecDeque<(IVec2, LightChannel)> = VecDeque::new();
+
+        let chunk_width = self.world_generator.config().chunk_width();
+        for (outside, inside) in chunk_border(chunk_position, chunk_width) {
+            if !self.is_loaded(outside) {
+                continue;
+            }
+            for channel in CHANNELS {
+                // Light that only came from a guess that was too bright has to be removed
+                if self.get_light(outside, channel) < self.assumed_light(outside, channel) {
+                    remove_light(self, inside, channel, &mut refill);
+                }
+                if self.get_light(inside, channel) < self.assumed_light(inside, channel) {
+                    remove_light(self, outside, channel, &mut refill);
+                }
+                refill.push_back((inside, channel));
+                refill.push_back((outside, channel));
+            }
+        }
+
+        propagate_light(self, &mut refill);
+    }
+}
+
+impl LightWorld for LoadedChunksLight<'_> {
+    fn is_loaded(&self, pos: IVec2) -> bool {
+        return self.chunks.contains_key(&BlockPos::from_ivec2(pos).chunk(self.world_generator.config().chunk_width()));
+    }
+
+    fn get_light(&self, pos: IVec2, channel: LightChannel) -> u8 {
+        let (chunk_position, index) = self.locate(pos);
+        return match self.chunks.get(&chunk_position) {
+            Some(chunk) => chunk.light.get(index, channel),
+            None => self.assumed_light(pos, channel),
+        };
+    }
+
+    fn set_light(&mut self, pos: IVec2, channel: LightChannel, level: u8) {
+        let (chunk_position, index) = self.locate(pos);
+        if let Some(chunk) = self.chunks.get_mut(&chunk_position) {
+            if chunk.light.get(index, channel) != level {
+                chunk.light.set(index, channel, level);
+                // The corners of the tiles on the other side of a border use this light too,
+                // even if it doesn't spread into them
+                let chunk_width = self.world_generator.config().chunk_width();
+                for y in -1..=1 {
+                    for x in -1..=1 {
+                        self.dirty_chunks.insert((BlockPos::from_ivec2(pos) + ivec2(x, y)).chunk(chunk_width));
+                    }
+                }
+            }
+        }
+    }
+
+    fn attenuation(&self, pos: IVec2) -> u8 {
+        let (chunk_position, index) = self.locate(pos);
+        return match self.chunks.get(&chunk_position) {
+            Some(chunk) => light_attenuation(self.block_registry.get(chunk.blocks(ChunkLayer::FOREGROUND).get(index))),
+            None => 1,
+        };
+    }
+
+    fn emission(&self, pos: IVec2) -> u8 {
+        let (chunk_position, index) = self.locate(pos);
+        return match self.chunks.get(&chunk_position) {
+            Some(chunk) => self.block_registry.get(chunk.blocks(ChunkLayer::FOREGROUND).get(index)).light_emission,
+            None => 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small rectangle of tiles, everything outside of it is dark and can't be lit.
+    struct TestWorld {
+        size: IVec2,
+        // (attenuation, emission) of every tile
+        blocks: HashMap<IVec2, (u8, u8)>,
+        light: HashMap<(IVec2, LightChannel), u8>,
+    }
+
+    impl TestWorld {
+        fn new(size: IVec2) -> TestWorld {
+            TestWorld {
+                size,
+                blocks: HashMap::new(),
+                light: HashMap::new(),
+            }
+        }
+
+        fn place(&mut self, pos: IVec2, attenuation: u8, emission: u8) {
+            self.blocks.insert(pos, (attenuation, emission));
+        }
+
+        // Lights everything from scratch, with the sky above the top row
+        fn full_light(&self) -> HashMap<(IVec2, LightChannel), u8> {
+            let mut world = TestWorld {
+                size: self.size,
+                blocks: self.blocks.clone(),
+                light: HashMap::new(),
+            };
+            let mut queue = VecDeque::new();
+            for x in 0..self.size.x {
+                for y in (0..self.size.y).rev() {
+                    let pos = ivec2(x, y);
+                    if world.attenuation(pos) != 1 {
+                        break;
+                    }
+                    world.set_light(pos, LightChannel::SUN, MAX_LIGHT);
+                    queue.push_back((pos, LightChannel::SUN));
+                }
+            }
+            for (pos, (_, emission)) in self.blocks.iter() {
+                if *emission > 0 {
+                    world.set_light(*pos, LightChannel::BLOCK, *emission);
+                    queue.push_back((*pos, LightChannel::BLOCK));
+                }
+            }
+            propagate_light(&mut world, &mut queue);
+            world.light.retain(|_, level| *level > 0);
+            return world.light;
+        }
+    }
+
+    impl LightWorld for TestWorld {
+        fn is_loaded(&self, pos: IVec2) -> bool {
+            return pos.cmpge(IVec2::ZERO).all() && pos.cmplt(self.size).all();
+        }
+        fn get_light(&self, pos: IVec2, channel: LightChannel) -> u8 {
+            if channel == LightChannel::SUN && pos.y == self.size.y && pos.x >= 0 && pos.x < self.size.x {
+                return MAX_LIGHT;
+            }
+            return *self.light.get(&(pos, channel)).unwrap_or(&0);
+        }
+        fn set_light(&mut self, pos: IVec2, channel: LightChannel, level: u8) {
+            if self.is_loaded(pos) {
+                self.light.insert((pos, channel), level);
+            }
+        }
+        fn attenuation(&self, pos: IVec2) -> u8 {
+            return self.blocks.get(&pos).map(|b| b.0).unwrap_or(1);
+        }
+        fn emission(&self, pos: IVec2) -> u8 {
+            return self.blocks.get(&pos).map(|b| b.1).unwrap_or(0);
+        }
+    }
+
+    #[test]
+    fn sunlight_goes_straight_down() {
+        let mut world = TestWorld::new(ivec2(5, 40));
+        world.place(ivec2(2, 20), OPAQUE_ATTENUATION, 0);
+        let light = world.full_light();
+
+        assert_eq!(light[&(ivec2(0, 0), LightChannel::SUN)], MAX_LIGHT);
+        assert_eq!(light[&(ivec2(2, 21), LightChannel::SUN)], MAX_LIGHT);
+        // The roof only gets the light that comes in from its sides
+        assert_eq!(light[&(ivec2(2, 20), LightChannel::SUN)], MAX_LIGHT - OPAQUE_ATTENUATION);
+        assert_eq!(light[&(ivec2(2, 19), LightChannel::SUN)], MAX_LIGHT - 1);
+    }
+
+    #[test]
+    fn block_light_fades_with_distance() {
+        let mut world = TestWorld::new(ivec2(40, 40));
+        // A roof, so the sun doesn't get in
+        for x in 0..40 {
+            world.place(ivec2(x, 39), 15, 0);
+        }
+        world.place(ivec2(20, 20), 1, 14);
+        let light = world.full_light();
+
+        assert_eq!(light[&(ivec2(20, 20), LightChannel::BLOCK)], 14);
+        assert_eq!(light[&(ivec2(25, 20), LightChannel::BLOCK)], 9);
+        assert_eq!(light[&(ivec2(23, 17), LightChannel::BLOCK)], 8);
+        assert_eq!(light.get(&(ivec2(20, 34), LightChannel::BLOCK)), None);
+        assert_eq!(light.get(&(ivec2(0, 0), LightChannel::SUN)), None);
+    }
+
+    #[test]
+    fn incremental_updates_match_full_relight() {
+        let mut world = TestWorld::new(ivec2(24, 24));
+        world.light = world.full_light();
+
+        // A fixed list of edits: roofs, walls, torches, and removing them again
+        let mut edits: Vec<(IVec2, u8, u8)> = vec![];
+        for x in 2..20 {
+            edits.push((ivec2(x, 15), OPAQUE_ATTENUATION, 0));
+        }
+        edits.push((ivec2(8, 8), 1, 14));
+        edits.push((ivec2(12, 15), 1, 0));
+        edits.push((ivec2(3, 3), 1, 10));
+        edits.push((ivec2(8, 8), 1, 0));
+        for y in 0..15 {
+            edits.push((ivec2(10, y), OPAQUE_ATTENUATION, 0));
+        }
+        edits.push((ivec2(10, 5), 1, 0));
+        edits.push((ivec2(12, 15), OPAQUE_ATTENUATION, 0));
+        edits.push((ivec2(9, 2), OPAQUE_ATTENUATION, 13));
+
+        for (pos, attenuation, emission) in edits {
+            world.place(pos, attenuation, emission);
+            update_light_at(&mut world, pos);
+
+            let mut incremental = world.light.clone();
+            incremental.retain(|_, level| *level > 0);
+            assert_eq!(incremental, world.full_light(), "after changing {}", pos);
+        }
+    }
+
+    #[test]
+    fn removing_a_torch_keeps_the_one_next_to_it() {
+        let mut world = TestWorld::new(ivec2(24, 24));
+        // A roof, so the sun doesn't get in
+        for x in 0..24 {
+            world.place(ivec2(x, 23), 15, 0);
+        }
+        world.place(ivec2(10, 10), 1, 14);
+        world.place(ivec2(13, 10), 1, 5);
+        world.light = world.full_light();
+        assert_eq!(world.get_light(ivec2(13, 10), LightChannel::BLOCK), 11);
+
+        world.place(ivec2(10, 10), 1, 0);
+        update_light_at(&mut world, ivec2(10, 10));
+
+        assert_eq!(world.get_light(ivec2(13, 10), LightChannel::BLOCK), 5);
+        assert_eq!(world.get_light(ivec2(11, 10), LightChannel::BLOCK), 3);
+        let mut incremental = world.light.clone();
+        incremental.retain(|_, level| *level > 0);
+        assert_eq!(incremental, world.full_light());
+    }
+}
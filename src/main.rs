@@ -1,16 +1,32 @@
-mod chunk;
-mod chunk_manager;
-mod collision;
-mod player;
-
-use chunk::{ChunkLayer, TILE_SIZE};
-use chunk_manager::{get_chunk_position, ChunkManager};
-use collision::{bounding_box::AxisAlignedRectangle, cast_ray_blocks};
+#![allow(clippy::needless_return)]
+
+use mijocraft_macroquad::{
+    block_edit::{EditHistory, MAX_HISTORY_EDITS},
+    block_registry::BlockRegistry,
+    chunk::ChunkLayer,
+    chunk_manager::{ChunkLoadingConfig, ChunkManager},
+    chunk_renderer::ChunkRenderer,
+    collision::bounding_box::AxisAlignedRectangle,
+    coordinates::BlockPos,
+    inventory::{Hotbar, InventoryUi},
+    level::Level,
+    placement::BlockPlacer,
+    world_config::{WorldConfig, CHUNK_WIDTH_VARIABLE, MAX_CHUNK_AREA, TILE_SIZE_VARIABLE},
+    world_generator::WorldGenerator,
+    world_save::WorldSave,
+};
 use macroquad::prelude::*;
 
-use player::Player;
+use std::{path::Path, sync::Arc};
+
+const WORLD_DIRECTORY: &str = "worlds/world";
+// The simulation always runs at this many ticks per second, no matter the frame rate
+const TICK_RATE: f32 = 60.0;
+const TICK_TIME: f32 = 1.0 / TICK_RATE;
+// After a very long frame only this many ticks are run, instead of freezing while catching up
+const MAX_TICKS_PER_FRAME: u32 = 8;
 
-fn window_conf() -> Conf {
+fn window_conf() -> macroquad::conf::Conf {
     let mut conf = Conf {
         window_title: "mijocraft".to_owned(),
         window_width: 1280,
@@ -20,34 +36,126 @@ fn window_conf() -> Conf {
     // Some(0) if you want to disable VSync
     // None if you want to enable VSync
     conf.platform.swap_interval = None;
-    return conf;
+    return macroquad::conf::Conf {
+        miniquad_conf: conf,
+        // Every chunk layer is drawn in a single draw call, by default macroquad
+        // would cut off the meshes of chunks wider than 28 blocks
+        draw_call_vertex_capacity: MAX_CHUNK_AREA * 4,
+        draw_call_index_capacity: MAX_CHUNK_AREA * 6,
+        ..Default::default()
+    };
 }
 
 #[macroquad::main(window_conf)]
 async fn main() {
-    let mut chunk_manager = ChunkManager::new().await;
+    let world_directory = Path::new(WORLD_DIRECTORY);
+    let block_registry = Arc::new(BlockRegistry::load("assets/blocks.ron").unwrap());
+    let mut level = match Level::load(world_directory).unwrap() {
+        Some(level) => {
+            if std::env::var_os(CHUNK_WIDTH_VARIABLE).is_some() || std::env::var_os(TILE_SIZE_VARIABLE).is_some() {
+                eprintln!("The world already exists, it keeps the sizes it was created with");
+            }
+            level
+        }
+        None => {
+            // New worlds get their sizes from MIJOCRAFT_CHUNK_WIDTH and MIJOCRAFT_TILE_SIZE
+            let config = match WorldConfig::from_env() {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Can't create the world: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let seed = ::rand::random();
+            let spawn_point = WorldGenerator::new(seed, &block_registry, config).spawn_point();
+            Level::new(seed, spawn_point, config)
+        }
+    };
+
+    // A world keeps the sizes it was created with
+    let config = level.world_config;
+    let tile_size = config.tile_size();
 
-    let mut player = Player::new(Vec2::ZERO);
+    let world_save = WorldSave::open(world_directory, block_registry.len(), config.chunk_area()).unwrap();
+    let mut chunk_manager = ChunkManager::new(
+        world_save,
+        WorldGenerator::new(level.seed, &block_registry, config),
+        block_registry.clone(),
+        ChunkLoadingConfig::default(),
+    );
+    let mut chunk_renderer = ChunkRenderer::load(config).await;
+
+    let mut player = level.create_player();
+    let mut hotbar = Hotbar::new(&block_registry);
+    let mut inventory_ui = InventoryUi::load().await;
+    let mut block_placer = BlockPlacer::load().await;
+    let mut edit_history = EditHistory::new(MAX_HISTORY_EDITS);
 
     let mut camera = Camera2D {
         ..Default::default()
     };
 
-    let mut zoom = 1.0;
+    let mut zoom = level.zoom;
     let mut mouse_pos: Option<Vec2> = None;
-    let mut block_mouse_pos: Option<IVec2> = None;
-    let mut current_block_layer: ChunkLayer = ChunkLayer::FOREGROUND;
+    let mut block_mouse_pos: Option<BlockPos> = None;
+    let mut world_mouse_pos: Option<Vec2> = None;
+    let mut current_block_layer: ChunkLayer = level.current_block_layer;
 
     let mut debug_f3: bool = false;
+    // Time that passed but wasn't simulated yet, always less than a tick after the ticks run
+    let mut tick_accumulator: f32 = 0.0;
+
+    // The window must not close before the world gets saved.
+    prevent_quit();
 
     loop {
+        level.play_time += get_frame_time() as f64;
+
+        if is_quit_requested() {
+            chunk_manager.save_modified_chunks();
+
+            level.store_player(&player);
+            level.current_block_layer = current_block_layer;
+            level.zoom = zoom;
+            if let Err(e) = level.save(world_directory) {
+                eprintln!("Failed to save the level: {}", e);
+            }
+            break;
+        }
+
         player.input();
-        player.update(&chunk_manager);
+
+        tick_accumulator += get_frame_time();
+        let mut ticks = 0;
+        while tick_accumulator >= TICK_TIME {
+            if ticks == MAX_TICKS_PER_FRAME {
+                tick_accumulator = 0.0;
+                break;
+            }
+            player.store_previous_position();
+            // The player waits in the air until the chunk it is in gets generated
+            if chunk_manager.is_chunk_loaded(BlockPos::from_world(player.get_position(), tile_size).chunk(config.chunk_width())) {
+                player.update(&chunk_manager, TICK_TIME);
+            }
+            tick_accumulator -= TICK_TIME;
+            ticks += 1;
+        }
+        // How far the rendering is between the last tick and the next one
+        let tick_alpha = tick_accumulator / TICK_TIME;
+
+        hotbar.input();
+        let mouse_over_ui = inventory_ui.input(&mut hotbar, &block_registry);
+        block_placer.input();
+        edit_history.input(&mut chunk_manager);
 
         if is_key_pressed(KeyCode::F3) {
             debug_f3 = !debug_f3;
         }
 
+        if is_key_pressed(KeyCode::F4) {
+            chunk_manager.set_meshing_mode(chunk_manager.meshing_mode().next());
+        }
+
         if is_key_down(KeyCode::LeftControl) {
             if mouse_wheel().1 > 0.0 {
                 zoom += 0.05;
@@ -59,11 +167,11 @@ async fn main() {
         }
 
         camera.zoom = vec2(
-            ((screen_height() / screen_width()) / TILE_SIZE as f32) / 6.0,
-            -((1.0 / TILE_SIZE as f32) / 6.0),
+            ((screen_height() / screen_width()) / tile_size) / 6.0,
+            -((1.0 / tile_size) / 6.0),
         ) * zoom;
 
-        camera.target = player.get_position();
+        camera.target = player.interpolated_position(tick_alpha);
 
         if mouse_delta_position().length() > 0.0 {
             mouse_pos = Some(vec2(mouse_position().0, mouse_position().1));
@@ -87,41 +195,54 @@ async fn main() {
             } * (screen_bottom_right_worldpos - screen_top_left_worldpos),
         };
 
-        chunk_manager.load_chunks_on_screen(&screen_aabb);
+        chunk_manager.load_chunks_on_screen(&screen_aabb, &player.bounding_box);
 
         if let Some(pos) = mouse_pos {
             let world_pos = camera.screen_to_world(pos);
-            let block_pos = (world_pos / TILE_SIZE as f32).floor().as_ivec2();
+            let block_pos = BlockPos::from_world(world_pos, tile_size);
 
-            if is_mouse_button_pressed(MouseButton::Left) {
-                chunk_manager.set_block(block_pos, current_block_layer, 0);
-            }
-            if is_mouse_button_pressed(MouseButton::Right) {
-                chunk_manager.set_block(block_pos, current_block_layer, 1);
+            if mouse_over_ui {
+                block_placer.cancel();
+            } else {
+                block_placer.update(
+                    world_pos,
+                    &player.bounding_box,
+                    current_block_layer,
+                    &mut chunk_manager,
+                    &mut edit_history,
+                    hotbar.selected_block(),
+                );
             }
 
             block_mouse_pos = Some(block_pos);
+            world_mouse_pos = Some(world_pos);
         }
 
         clear_background(Color::from_hex(0x628fd9));
 
         set_camera(&camera);
 
-        chunk_manager.draw(&screen_aabb, debug_f3);
-        player.draw();
-
+        chunk_renderer.update(&mut chunk_manager);
+        chunk_renderer.draw(&chunk_manager, &screen_aabb, debug_f3);
+        player.draw(tick_alpha);
 
-        if let Some(pos) = block_mouse_pos {
-            draw_rectangle(
-                pos.x as f32 * TILE_SIZE as f32,
-                pos.y as f32 * TILE_SIZE as f32,
-                TILE_SIZE as f32,
-                TILE_SIZE as f32,
-                Color::new(1.0, 1.0, 1.0, 0.5),
-            );
+        // The blocks that would be changed by clicking, with the current place mode
+        if let Some(pos) = world_mouse_pos {
+            for pos in block_placer.shape(pos, &player.bounding_box, current_block_layer, &chunk_manager) {
+                draw_rectangle(
+                    pos.x() as f32 * tile_size,
+                    pos.y() as f32 * tile_size,
+                    tile_size,
+                    tile_size,
+                    Color::new(1.0, 1.0, 1.0, 0.5),
+                );
+            }
         }
 
         set_default_camera();
+        inventory_ui.draw(&hotbar, &block_registry, chunk_renderer.blocks_atlas_texture());
+        block_placer.draw_ui(current_block_layer);
+
         if !debug_f3 {
             draw_text("Press F3 for debug", 8.0, 24.0, 32.0, WHITE);
         } else {
@@ -130,30 +251,38 @@ async fn main() {
                 format!("Position: {}", player.bounding_box.center_pos),
                 format!("Current cursor layer: {}", current_block_layer),
                 format!("Zoom: {}x", zoom),
+                format!("Seed: {}", level.seed),
+                format!("Play time: {}s", level.play_time.floor()),
                 "\n".to_string(),
                 format!(
                     "Block position: {}",
-                    (player.bounding_box.center_pos / TILE_SIZE as f32)
-                        .floor()
-                        .as_ivec2()
+                    BlockPos::from_world(player.bounding_box.center_pos, tile_size)
                 ),
                 format!(
                     "Chunk position: {}",
-                    get_chunk_position(
-                        (player.bounding_box.center_pos / TILE_SIZE as f32)
-                            .floor()
-                            .as_ivec2()
-                    )
+                    BlockPos::from_world(player.bounding_box.center_pos, tile_size).chunk(config.chunk_width())
+                ),
+                format!(
+                    "Biome: {}",
+                    chunk_manager.world_generator().biome_at(BlockPos::from_world(player.bounding_box.center_pos, tile_size).x())
+                ),
+                format!(
+                    "Chunk width: {} blocks, tile size: {}",
+                    config.chunk_width(),
+                    tile_size
                 ),
                 "\n".to_string(),
                 format!(
                     "Loaded Chunks: {}",
                     chunk_manager.get_loaded_chunks_amount()
                 ),
+                format!("Meshing (F4): {}", chunk_manager.meshing_mode()),
             ];
 
             if let Some(pos) = block_mouse_pos {
                 strings.insert(2, format!("Cursor position: {}", pos));
+                let cursor_block = block_registry.get(chunk_manager.get_block(pos, current_block_layer));
+                strings.insert(3, format!("Cursor block: {}", cursor_block.name));
             }
 
             let mut cur_y = 0.0;
@@ -0,0 +1,465 @@
+use std::collections::{HashSet, VecDeque};
+
+use macroquad::prelude::*;
+
+use crate::{
+    block_edit::EditHistory,
+    block_registry::AIR,
+    chunk::ChunkLayer,
+    chunk_manager::ChunkManager,
+    collision::{bounding_box::AxisAlignedRectangle, cast_ray_blocks, RayFilter},
+    coordinates::BlockPos,
+};
+
+// How far from the center of the player blocks can be reached in survival mode, in blocks
+pub const SURVIVAL_REACH: f32 = 6.0;
+
+// A flood fill that would change more blocks than this doesn't do anything,
+// so clicking on the sky doesn't try to fill the whole world.
+pub const MAX_FILL_AREA: usize = 4096;
+
+const LAYER_ICON_SIZE: f32 = 32.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaceMode {
+    SINGLE,
+    // Drag to draw a line from where the mouse was pressed
+    LINE,
+    // Drag to draw a rectangle between where the mouse was pressed and the cursor
+    RECTANGLE,
+    // Same as RECTANGLE, but only the border
+    OUTLINE,
+    // Replaces the area of the same block that was clicked
+    FILL,
+}
+
+impl std::fmt::Display for PlaceMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlaceMode::SINGLE => write!(f, "Single"),
+            PlaceMode::LINE => write!(f, "Line"),
+            PlaceMode::RECTANGLE => write!(f, "Rectangle"),
+            PlaceMode::OUTLINE => write!(f, "Rectangle outline"),
+            PlaceMode::FILL => write!(f, "Fill"),
+        }
+    }
+}
+
+impl PlaceMode {
+    const ALL: [PlaceMode; 5] = [
+        PlaceMode::SINGLE,
+        PlaceMode::LINE,
+        PlaceMode::RECTANGLE,
+        PlaceMode::OUTLINE,
+        PlaceMode::FILL,
+    ];
+
+    pub fn next(&self) -> PlaceMode {
+        let index = PlaceMode::ALL.iter().position(|mode| mode == self).unwrap();
+        return PlaceMode::ALL[(index + 1) % PlaceMode::ALL.len()];
+    }
+}
+
+// Places and breaks blocks with the mouse, in the current place mode.
+// The left button breaks blocks and the right button places the selected one.
+//
+// In creative mode any block on the screen can be changed, but in survival mode only
+// the first solid block between the player and the cursor can be broken, as long
+// as it's in reach, and blocks can only be placed against the side of another one.
+pub struct BlockPlacer {
+    mode: PlaceMode,
+    survival: bool,
+    // Where the mouse was pressed and which button, while drawing a line or a rectangle
+    drag: Option<(BlockPos, MouseButton)>,
+    // Has an icon for each layer, see draw_ui
+    place_modes_texture: Texture2D,
+}
+
+impl BlockPlacer {
+    pub async fn load() -> BlockPlacer {
+        let place_modes_texture = load_texture("assets/textures/place_modes.png").await.unwrap();
+        place_modes_texture.set_filter(FilterMode::Nearest);
+
+        return BlockPlacer {
+            mode: PlaceMode::SINGLE,
+            survival: false,
+            drag: None,
+            place_modes_texture,
+        };
+    }
+
+    pub fn input(&mut self) {
+        if is_key_pressed(KeyCode::Q) {
+            self.mode = self.mode.next();
+            self.drag = None;
+        }
+        if is_key_pressed(KeyCode::G) {
+            self.survival = !self.survival;
+            self.drag = None;
+        }
+    }
+
+    // Has to be called every frame that the cursor is on the world, the cursor is in world units.
+    // Every click is recorded in the history as a single action.
+    pub fn update(
+        &mut self,
+        cursor: Vec2,
+        player: &AxisAlignedRectangle,
+        layer: ChunkLayer,
+        chunk_manager: &mut ChunkManager,
+        history: &mut EditHistory,
+        selected_block: usize,
+    ) {
+        if self.survival {
+            self.update_survival(cursor, player, layer, chunk_manager, history, selected_block);
+            return;
+        }
+
+        let cursor = BlockPos::from_world(cursor, chunk_manager.config().tile_size());
+        for button in [MouseButton::Left, MouseButton::Right] {
+            if !is_mouse_button_pressed(button) {
+                continue;
+            }
+            let block = block_for_button(button, selected_block);
+            match self.mode {
+                PlaceMode::SINGLE | PlaceMode::FILL => {
+                    let shape = self.creative_shape(cursor, layer, chunk_manager);
+                    place_shape(chunk_manager, history, &shape, layer, block);
+                }
+                PlaceMode::LINE | PlaceMode::RECTANGLE | PlaceMode::OUTLINE => {
+                    self.drag = Some((cursor, button));
+                }
+            }
+        }
+
+        if let Some((_, button)) = self.drag {
+            if is_mouse_button_released(button) {
+                let shape = self.creative_shape(cursor, layer, chunk_manager);
+                place_shape(chunk_manager, history, &shape, layer, block_for_button(button, selected_block));
+                self.drag = None;
+            }
+        }
+    }
+
+    fn update_survival(
+        &mut self,
+        cursor: Vec2,
+        player: &AxisAlignedRectangle,
+        layer: ChunkLayer,
+        chunk_manager: &mut ChunkManager,
+        history: &mut EditHistory,
+        selected_block: usize,
+    ) {
+        let Some((hit_block, face_block)) = survival_target(cursor, player, layer, chunk_manager) else {
+            return;
+        };
+
+        if is_mouse_button_pressed(MouseButton::Left) {
+            place_shape(chunk_manager, history, &[hit_block], layer, AIR);
+        }
+        if is_mouse_button_pressed(MouseButton::Right) {
+            let collidable = chunk_manager.block_registry().get(selected_block).collidable;
+            let face_is_replaceable = is_replaceable(chunk_manager.get_block(face_block, layer), chunk_manager);
+            // The player would get stuck inside of the block
+            let inside_player = layer == ChunkLayer::FOREGROUND && collidable && player.intersects(&block_aabb(face_block, chunk_manager.config().tile_size()));
+            if face_is_replaceable && !inside_player {
+                place_shape(chunk_manager, history, &[face_block], layer, selected_block);
+            }
+        }
+    }
+
+    // Stops drawing a line or a rectangle without placing it.
+    pub fn cancel(&mut self) {
+        self.drag = None;
+    }
+
+    // The blocks that would be changed with the cursor here, used for the preview.
+    pub fn shape(&self, cursor: Vec2, player: &AxisAlignedRectangle, layer: ChunkLayer, chunk_manager: &ChunkManager) -> Vec<BlockPos> {
+        if self.survival {
+            return match survival_target(cursor, player, layer, chunk_manager) {
+                Some((hit_block, _)) => vec![hit_block],
+                None => vec![],
+            };
+        }
+        return self.creative_shape(BlockPos::from_world(cursor, chunk_manager.config().tile_size()), layer, chunk_manager);
+    }
+
+    fn creative_shape(&self, cursor: BlockPos, layer: ChunkLayer, chunk_manager: &ChunkManager) -> Vec<BlockPos> {
+        // The shapes are plain geometry, they don't know about blocks
+        let positions = match (self.mode, self.drag) {
+            (PlaceMode::LINE, Some((start, _))) => line_positions(start.to_ivec2(), cursor.to_ivec2()),
+            (PlaceMode::RECTANGLE, Some((start, _))) => rectangle_positions(start.to_ivec2(), cursor.to_ivec2(), false),
+            (PlaceMode::OUTLINE, Some((start, _))) => rectangle_positions(start.to_ivec2(), cursor.to_ivec2(), true),
+            (PlaceMode::FILL, _) => {
+                let target = chunk_manager.get_block(cursor, layer);
+                flood_fill_positions(cursor.to_ivec2(), MAX_FILL_AREA, |pos| {
+                    let pos = BlockPos::from_ivec2(pos);
+                    chunk_manager.is_chunk_loaded(pos.chunk(chunk_manager.config().chunk_width())) && chunk_manager.get_block(pos, layer) == target
+                })
+                .unwrap_or_default()
+            }
+            _ => vec![cursor.to_ivec2()],
+        };
+        return positions.into_iter().map(BlockPos::from_ivec2).collect();
+    }
+
+    // The current mode in the top right corner of the screen, next to the icon of the current layer.
+    pub fn draw_ui(&self, layer: ChunkLayer) {
+        let icon_index = match layer {
+            ChunkLayer::BACKGROUND => 0.0,
+            ChunkLayer::FOREGROUND => 1.0,
+        };
+        let text = match self.survival {
+            true => "Survival [G]".to_string(),
+            false => format!("{} [Q] - Creative [G]", self.mode),
+        };
+        let size = measure_text(&text, None, 32, 1.0);
+        let x = screen_width() - size.width - LAYER_ICON_SIZE - 24.0;
+
+        draw_rectangle(x - 8.0, 8.0, size.width + LAYER_ICON_SIZE + 24.0, LAYER_ICON_SIZE + 8.0, color_u8!(0.0, 0.0, 0.0, 128.0));
+        draw_texture_ex(
+            &self.place_modes_texture,
+            x - 4.0,
+            12.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(Vec2::splat(LAYER_ICON_SIZE)),
+                source: Some(Rect::new(icon_index * 8.0, 0.0, 8.0, 8.0)),
+                ..Default::default()
+            },
+        );
+        draw_text(&text, x + LAYER_ICON_SIZE + 4.0, 12.0 + LAYER_ICON_SIZE / 2.0 + size.offset_y / 2.0, 32.0, WHITE);
+    }
+}
+
+fn place_shape(chunk_manager: &mut ChunkManager, history: &mut EditHistory, shape: &[BlockPos], layer: ChunkLayer, block: usize) {
+    let mut batch = chunk_manager.edit_batch();
+    for pos in shape {
+        batch.set_block(*pos, layer, block);
+    }
+    batch.finish_recorded(history);
+}
+
+// The block the player is pointing at and the tile in front of the side that was hit, or None if
+// there isn't a block between the player and the cursor that is in reach. Blocks that don't fill
+// the tile, like torches and flowers, count too, otherwise they could never be broken, but the
+// player points through fluids.
+fn survival_target(cursor: Vec2, player: &AxisAlignedRectangle, layer: ChunkLayer, chunk_manager: &ChunkManager) -> Option<(BlockPos, BlockPos)> {
+    let origin = player.center_pos;
+    // Blocks behind the cursor can't be reached
+    let reach = (cursor - origin).length().min(SURVIVAL_REACH * chunk_manager.config().tile_size());
+    let hit = cast_ray_blocks(chunk_manager, chunk_manager.block_registry(), layer, RayFilter::TARGETABLE, origin, cursor, reach)?;
+    return Some((hit.block_position, hit.block_position + hit.normal));
+}
+
+// Air, and blocks like water and tall grass that placing a block just overwrites.
+fn is_replaceable(block_id: usize, chunk_manager: &ChunkManager) -> bool {
+    let block = chunk_manager.block_registry().get(block_id);
+    return !block.solid && !block.collidable;
+}
+
+// Slightly smaller than the tile, so blocks right next to something don't count as touching it
+fn block_aabb(block_position: BlockPos, tile_size: f32) -> AxisAlignedRectangle {
+    return AxisAlignedRectangle {
+        center_pos: (block_position.to_ivec2().as_vec2() + Vec2::splat(0.5)) * tile_size,
+        size: Vec2::splat(tile_size - 0.1),
+    };
+}
+
+fn block_for_button(button: MouseButton, selected_block: usize) -> usize {
+    return match button {
+        MouseButton::Right => selected_block,
+        _ => AIR,
+    };
+}
+
+// Bresenham's line algorithm, both ends are included.
+pub fn line_positions(start: IVec2, end: IVec2) -> Vec<IVec2> {
+    let delta = (end - start).abs();
+    let step = (end - start).signum();
+    let mut error = delta.x - delta.y;
+    let mut pos = start;
+    let mut positions = vec![pos];
+
+    while pos != end {
+        let doubled_error = error * 2;
+        if doubled_error > -delta.y {
+            error -= delta.y;
+            pos.x += step.x;
+        }
+        if doubled_error < delta.x {
+            error += delta.x;
+            pos.y += step.y;
+        }
+        positions.push(pos);
+    }
+
+    return positions;
+}
+
+// Both corners are included, no matter which one is the smallest.
+pub fn rectangle_positions(corner_a: IVec2, corner_b: IVec2, hollow: bool) -> Vec<IVec2> {
+    let min = corner_a.min(corner_b);
+    let max = corner_a.max(corner_b);
+    let mut positions = vec![];
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let on_border = x == min.x || x == max.x || y == min.y || y == max.y;
+            if !hollow || on_border {
+                positions.push(ivec2(x, y));
+            }
+        }
+    }
+    return positions;
+}
+
+// Every position connected to start by its sides where is_inside is true. Returns None
+// if there would be more than max_area positions, since that area is probably open.
+pub fn flood_fill_positions(start: IVec2, max_area: usize, is_inside: impl Fn(IVec2) -> bool) -> Option<Vec<IVec2>> {
+    if !is_inside(start) {
+        return Some(vec![]);
+    }
+
+    let mut visited: HashSet<IVec2> = HashSet::from([start]);
+    let mut queue: VecDeque<IVec2> = VecDeque::from([start]);
+    let mut positions = vec![];
+
+    while let Some(pos) = queue.pop_front() {
+        positions.push(pos);
+        if positions.len() > max_area {
+            return None;
+        }
+        for direction in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+            let neighbour = pos + direction;
+            if is_inside(neighbour) && visited.insert(neighbour) {
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    return Some(positions);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block_registry::BlockRegistry,
+        chunk_manager::ChunkLoadingConfig,
+        world_config::WorldConfig,
+        world_generator::WorldGenerator,
+        world_save::WorldSave,
+    };
+    use std::{path::Path, sync::Arc};
+
+    #[test]
+    fn lines_are_connected_in_every_direction() {
+        assert_eq!(line_positions(ivec2(0, 0), ivec2(3, 0)), vec![ivec2(0, 0), ivec2(1, 0), ivec2(2, 0), ivec2(3, 0)]);
+        assert_eq!(line_positions(ivec2(2, 2), ivec2(2, 2)), vec![ivec2(2, 2)]);
+        assert_eq!(line_positions(ivec2(0, 0), ivec2(-2, -2)), vec![ivec2(0, 0), ivec2(-1, -1), ivec2(-2, -2)]);
+
+        for end in [ivec2(7, 3), ivec2(-3, 7), ivec2(-7, -3), ivec2(3, -7)] {
+            let line = line_positions(IVec2::ZERO, end);
+            assert_eq!(line.len() as i32, end.abs().max_element() + 1);
+            assert_eq!(*line.last().unwrap(), end);
+            for pair in line.windows(2) {
+                assert_eq!((pair[1] - pair[0]).abs().max_element(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn rectangles_include_both_corners() {
+        assert_eq!(rectangle_positions(ivec2(2, 1), ivec2(0, 0), false).len(), 6);
+        let hollow = rectangle_positions(ivec2(-2, -2), ivec2(2, 2), true);
+        assert_eq!(hollow.len(), 16);
+        assert!(!hollow.contains(&IVec2::ZERO));
+    }
+
+    #[test]
+    fn flood_fill_stops_at_walls_and_at_the_limit() {
+        // A 3x3 room with walls around it
+        let room = |pos: IVec2| pos.cmpge(IVec2::ZERO).all() && pos.cmplt(IVec2::splat(3)).all();
+        assert_eq!(flood_fill_positions(ivec2(1, 1), 9, room).unwrap().len(), 9);
+        assert_eq!(flood_fill_positions(ivec2(1, 1), 8, room), None);
+        assert_eq!(flood_fill_positions(ivec2(5, 5), 8, room), Some(vec![]));
+    }
+
+    fn test_world(directory: &Path) -> ChunkManager {
+        let block_registry = Arc::new(BlockRegistry::load("assets/blocks.ron").unwrap());
+        let config = WorldConfig::default();
+        let mut chunk_manager = ChunkManager::new(
+            WorldSave::open(directory, block_registry.len(), config.chunk_area()).unwrap(),
+            WorldGenerator::new(0, &block_registry, config),
+            block_registry,
+            ChunkLoadingConfig::default(),
+        );
+        // High up in the sky, so there's nothing else in the way
+        chunk_manager.load_chunk_now(BlockPos::new(0, 100).chunk(config.chunk_width()));
+        return chunk_manager;
+    }
+
+    fn player_at(tile: IVec2) -> AxisAlignedRectangle {
+        let tile_size = WorldConfig::default().tile_size();
+        return AxisAlignedRectangle {
+            center_pos: (tile.as_vec2() + Vec2::splat(0.5)) * tile_size,
+            size: Vec2::splat(tile_size * 0.75),
+        };
+    }
+
+    fn tile_center(tile: IVec2) -> Vec2 {
+        return (tile.as_vec2() + Vec2::splat(0.5)) * WorldConfig::default().tile_size();
+    }
+
+    #[test]
+    fn survival_can_target_blocks_that_dont_fill_the_tile() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut chunk_manager = test_world(directory.path());
+        let flower = chunk_manager.block_registry().id_by_name("flower").unwrap();
+        let stone = chunk_manager.block_registry().id_by_name("stone").unwrap();
+        chunk_manager.set_block(BlockPos::new(3, 100), ChunkLayer::FOREGROUND, flower);
+        chunk_manager.set_block(BlockPos::new(4, 100), ChunkLayer::FOREGROUND, stone);
+
+        let target = survival_target(tile_center(ivec2(4, 100)), &player_at(ivec2(0, 100)), ChunkLayer::FOREGROUND, &chunk_manager);
+        assert_eq!(target, Some((BlockPos::new(3, 100), BlockPos::new(2, 100))));
+    }
+
+    #[test]
+    fn survival_looks_past_what_the_player_stands_in() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut chunk_manager = test_world(directory.path());
+        let stone = chunk_manager.block_registry().id_by_name("stone").unwrap();
+        chunk_manager.set_block(BlockPos::new(4, 100), ChunkLayer::FOREGROUND, stone);
+
+        for standing_in in ["tall_grass", "water"] {
+            let block = chunk_manager.block_registry().id_by_name(standing_in).unwrap();
+            chunk_manager.set_block(BlockPos::new(0, 100), ChunkLayer::FOREGROUND, block);
+            let target = survival_target(tile_center(ivec2(4, 100)), &player_at(ivec2(0, 100)), ChunkLayer::FOREGROUND, &chunk_manager);
+            assert_eq!(target, Some((BlockPos::new(4, 100), BlockPos::new(3, 100))), "standing in {}", standing_in);
+        }
+    }
+
+    #[test]
+    fn survival_reaches_blocks_under_water() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut chunk_manager = test_world(directory.path());
+        let registry = chunk_manager.block_registry();
+        let (water, stone, tall_grass) = (
+            registry.id_by_name("water").unwrap(),
+            registry.id_by_name("stone").unwrap(),
+            registry.id_by_name("tall_grass").unwrap(),
+        );
+        for x in 1..4 {
+            chunk_manager.set_block(BlockPos::new(x, 100), ChunkLayer::FOREGROUND, water);
+        }
+        chunk_manager.set_block(BlockPos::new(4, 100), ChunkLayer::FOREGROUND, stone);
+
+        let target = survival_target(tile_center(ivec2(4, 100)), &player_at(ivec2(0, 100)), ChunkLayer::FOREGROUND, &chunk_manager);
+        assert_eq!(target, Some((BlockPos::new(4, 100), BlockPos::new(3, 100))));
+        // Water and plants get replaced by placed blocks, but other blocks don't
+        assert!(is_replaceable(water, &chunk_manager));
+        assert!(is_replaceable(tall_grass, &chunk_manager));
+        assert!(is_replaceable(AIR, &chunk_manager));
+        assert!(!is_replaceable(stone, &chunk_manager));
+    }
+}
@@ -3,37 +3,53 @@ use std::f32::consts::FRAC_PI_2;
 use macroquad::prelude::*;
 
 use crate::{
-    chunk::{ChunkLayer, TILE_SIZE},
+    block_registry::BlockRegistry,
+    chunk::ChunkLayer,
     chunk_manager::ChunkManager,
-    collision::{self, bounding_box::AxisAlignedRectangle},
+    collision::{self, bounding_box::AxisAlignedRectangle, Contacts},
+    coordinates::BlockPos,
 };
 
+// The width and height of the player, in tiles
+const PLAYER_SIZE: f32 = 0.75;
+
 pub struct Player {
     pub velocity: Vec2,
     pub floored: bool,
+    // The sides of the player that touched a block on the last update
+    pub contacts: Contacts,
     pub direction: isize,
     pub sprite_rotation: f32,
     pub noclip: bool,
     pub bounding_box: AxisAlignedRectangle,
+    // Where the player was before the last update, the player is drawn
+    // between this and the current position, see interpolated_position
+    pub previous_position: Vec2,
+    // The tile size of the world, the player is a bit smaller than a tile and moves in tiles per second
+    tile_size: f32,
 }
 
 impl Player {
-    pub fn new(center_pos: Vec2) -> Player {
+    pub fn new(center_pos: Vec2, tile_size: f32) -> Player {
         Player {
             velocity: Vec2::ZERO,
             floored: false,
+            contacts: Contacts::default(),
             direction: 0,
             sprite_rotation: 0.0,
             noclip: false,
             bounding_box: AxisAlignedRectangle {
                 center_pos,
-                size: Vec2::splat(TILE_SIZE as f32 - 8.0),
+                // 24 world units with the default tile size
+                size: Vec2::splat(tile_size * PLAYER_SIZE),
             },
+            previous_position: center_pos,
+            tile_size,
         }
     }
 
     pub fn input(&mut self) {
-        let mut speed = 10.0 * TILE_SIZE as f32;
+        let mut speed = 10.0 * self.tile_size;
         if is_key_down(KeyCode::LeftControl) {
             speed *= 4.0;
         }
@@ -64,110 +80,92 @@ impl Player {
             }
 
             self.velocity = self.velocity.normalize_or_zero() * speed;
-        } else {
-            if self.floored {
-                if is_key_down(KeyCode::Space)
-                    || is_key_down(KeyCode::Up)
-                    || is_key_down(KeyCode::W)
-                {
-                    self.velocity.y += 16.0 * TILE_SIZE as f32;
-                }
-            }
+        } else if self.floored
+            && (is_key_down(KeyCode::Space)
+                || is_key_down(KeyCode::Up)
+                || is_key_down(KeyCode::W))
+        {
+            // Input can be read more than once between two updates, so this can't add to the velocity
+            self.velocity.y = 16.0 * self.tile_size;
         }
 
         if is_key_pressed(KeyCode::F) {
             self.noclip = !self.noclip;
             self.velocity = Vec2::ZERO;
             self.floored = false;
+            self.contacts = Contacts::default();
         }
     }
 
-    pub fn update(&mut self, chunk_manager: &ChunkManager) {
+    // Moves the player forward by dt seconds, it's always called with the same dt,
+    // so the player moves the same way no matter how fast the game is running.
+    pub fn update(&mut self, chunk_manager: &ChunkManager, dt: f32) {
+        // Both in tiles
         const GRAVITY_ACCEL: f32 = 98.07;
-        const TERMINAL_GRAVITY: f32 = 530.0;
+        const TERMINAL_GRAVITY: f32 = 16.5625;
+        let terminal_velocity = TERMINAL_GRAVITY * self.tile_size;
 
         // Apply gravity
         if !self.noclip {
-            if self.velocity.y > -TERMINAL_GRAVITY {
-                self.velocity.y -= (GRAVITY_ACCEL * TILE_SIZE as f32) * get_frame_time();
-            } else if self.velocity.y < -TERMINAL_GRAVITY {
-                self.velocity.y = -TERMINAL_GRAVITY;
+            if self.velocity.y > -terminal_velocity {
+                self.velocity.y -= (GRAVITY_ACCEL * self.tile_size) * dt;
+            } else if self.velocity.y < -terminal_velocity {
+                self.velocity.y = -terminal_velocity;
             }
         }
 
-        self.move_player(chunk_manager);
+        self.move_player(chunk_manager, chunk_manager.block_registry(), dt);
 
         if !self.floored {
-            self.sprite_rotation -= (9.6 * get_frame_time()) * self.direction as f32;
+            self.sprite_rotation -= (9.6 * dt) * self.direction as f32;
         } else {
             self.sprite_rotation = (self.sprite_rotation / FRAC_PI_2).round() * FRAC_PI_2;
         }
     }
 
-    // Why is the player climbing walls??? :sob:
-    // =========================================
-
-    fn move_player(&mut self, chunk_manager: &ChunkManager) {
+    fn move_player(&mut self, chunk_manager: &ChunkManager, block_registry: &BlockRegistry, dt: f32) {
         if self.noclip {
-            self.bounding_box.center_pos += self.velocity * get_frame_time();
+            self.bounding_box.center_pos += self.velocity * dt;
             return;
         }
-        let collision::RectangleCorners {
-            top_right,
-            top_left,
-            bottom_right,
-            bottom_left,
-        } = self.bounding_box.as_drectangle().corners();
-
-        self.floored = false;
-
-        let get_corner_overlap =
-            |corner: Vec2, add_x: bool, add_y: bool, velocity: Vec2| -> Option<(Vec2, Vec2)> {
-                let next_frame = corner + (velocity * get_frame_time());
-                if chunk_manager.get_block((next_frame / TILE_SIZE as f32).floor().as_ivec2(), ChunkLayer::FOREGROUND) > 0 {
-                    let to_block = (next_frame / TILE_SIZE as f32).floor() * TILE_SIZE as f32;
-                    let added = to_block
-                        + vec2(
-                            TILE_SIZE as f32 * add_x as i32 as f32,
-                            TILE_SIZE as f32 * add_y as i32 as f32,
-                        );
-                    return Some((Vec2::abs(next_frame - added), added));
-                } else {
-                    return None;
-                }
-            };
-
-        let mut solve_collision = |corner: Vec2, add_x: bool, add_y: bool| {
-            let Some((overlap, collided_block_pos)) =
-                get_corner_overlap(corner, add_x, add_y, self.velocity)
-            else {
-                return;
-            };
-            let min = overlap.x.min(overlap.y);
-            if min == overlap.x {
-                self.velocity.x = 0.0;
-                self.bounding_box.center_pos.x -= corner.x - collided_block_pos.x;
-            } else if min == overlap.y {
-                self.velocity.y = 0.0;
-                self.bounding_box.center_pos.y -= corner.y - collided_block_pos.y;
-                if add_y {
-                    self.floored = true
-                }
+
+        // Chunks that are still being generated are walls, otherwise the player
+        // would fall through the ground before it's there
+        let chunk_width = chunk_manager.config().chunk_width();
+        self.contacts = collision::move_and_collide(&mut self.bounding_box, self.velocity * dt, self.tile_size, |block_position| {
+            let block_position = BlockPos::from_ivec2(block_position);
+            if !chunk_manager.is_chunk_loaded(block_position.chunk(chunk_width)) {
+                return true;
             }
-        };
+            return block_registry.get(chunk_manager.get_block(block_position, ChunkLayer::FOREGROUND)).collidable;
+        });
 
-        solve_collision(bottom_left, true, true);
-        solve_collision(bottom_right, false, true);
-        solve_collision(top_right, false, false);
-        solve_collision(top_left, true, false);
+        if self.contacts.left || self.contacts.right {
+            self.velocity.x = 0.0;
+        }
+        if self.contacts.bottom || self.contacts.top {
+            self.velocity.y = 0.0;
+        }
+        self.floored = self.contacts.bottom;
+    }
 
-        self.bounding_box.center_pos += self.velocity * get_frame_time();
+    // Has to be called before every update, even the ones that are skipped.
+    pub fn store_previous_position(&mut self) {
+        self.previous_position = self.bounding_box.center_pos;
     }
 
-    pub fn draw(&self) {
-        self.bounding_box
-            .as_drectangle()
-            .draw_center_rotated(RED, self.sprite_rotation);
+    // Where the player is between the last two updates, alpha goes from 0 (the previous
+    // position) to 1 (the current one). Used to draw the player smoothly between updates.
+    pub fn interpolated_position(&self, alpha: f32) -> Vec2 {
+        return self.previous_position.lerp(self.bounding_box.center_pos, alpha);
+    }
+
+    pub fn draw(&self, alpha: f32) {
+        let rectangle = collision::Rectangle {
+            pos_bottom_left: self.interpolated_position(alpha) - self.bounding_box.size * 0.5,
+            size: self.bounding_box.size,
+        };
+        rectangle.draw_center_rotated(RED, self.sprite_rotation);
     }
 
     pub fn get_position(&self) -> Vec2 {
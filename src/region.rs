@@ -0,0 +1,348 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use macroquad::prelude::*;
+
+use crate::{
+    chunk::BLOCK_LAYOUT_VERSION,
+    coordinates::ChunkPos,
+};
+
+// A region file stores REGION_WIDTH * REGION_WIDTH chunks.
+//
+// Layout (everything is little endian):
+// - 4 bytes: magic, "MJRG"
+// - u32: region format version
+// - u32: block layout version the chunks were saved with
+// - u32: how many blocks the block registry had when the chunks were saved
+// - REGION_AREA * (u32 offset, u32 length): where each chunk is in the file,
+//   a length of 0 means the chunk was never saved
+// - the zlib compressed chunks, each one being the foreground blocks
+//   followed by the background blocks as u16s. The chunk width isn't stored,
+//   it's in the level file, a chunk of another size fails to load.
+pub const REGION_WIDTH: i32 = 32;
+pub const REGION_AREA: usize = (REGION_WIDTH * REGION_WIDTH) as usize;
+pub const REGION_FORMAT_VERSION: u32 = 1;
+
+const REGION_MAGIC: &[u8; 4] = b"MJRG";
+const HEADER_SIZE: usize = 16 + REGION_AREA * 8;
+
+// The foreground and the background blocks, both as long as the area of the chunk
+pub type ChunkBlocks = (Vec<usize>, Vec<usize>);
+
+// Every function in this list converts the blocks of a chunk from one block layout version
+// to the next one, the first function converts from version 1 to 2 and so on.
+// When the block IDs are changed, BLOCK_LAYOUT_VERSION has to be bumped and a
+// migration has to be added here, otherwise old worlds will load with the wrong blocks.
+const BLOCK_LAYOUT_MIGRATIONS: &[fn(&mut ChunkBlocks)] = &[];
+
+pub struct RegionFile {
+    block_layout_version: u32,
+    // The amount of blocks in the registry when this region was saved, including air
+    saved_block_count: u32,
+    // The amount of blocks in the registry right now
+    block_count: u32,
+    // How many blocks are in each layer of a chunk
+    chunk_area: usize,
+    // The compressed data of every chunk inside this region
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl RegionFile {
+    pub fn new(block_count: usize, chunk_area: usize) -> RegionFile {
+        RegionFile {
+            block_layout_version: BLOCK_LAYOUT_VERSION,
+            saved_block_count: block_count as u32,
+            block_count: block_count as u32,
+            chunk_area,
+            chunks: vec![None; REGION_AREA],
+        }
+    }
+
+    pub fn read(path: &Path, block_count: usize, chunk_area: usize) -> io::Result<RegionFile> {
+        let bytes = fs::read(path)?;
+        return RegionFile::from_bytes(&bytes, block_count, chunk_area);
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        // Writing to another file first means a crash while saving
+        // can't leave a half written region behind.
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, self.to_bytes())?;
+        return fs::rename(temporary_path, path);
+    }
+
+    pub fn from_bytes(bytes: &[u8], block_count: usize, chunk_area: usize) -> io::Result<RegionFile> {
+        if bytes.len() < HEADER_SIZE || &bytes[0..4] != REGION_MAGIC {
+            return Err(invalid_data("not a region file".to_string()));
+        }
+
+        let format_version = read_u32(bytes, 4);
+        if format_version > REGION_FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "region format version {} is newer than the supported version {}",
+                format_version, REGION_FORMAT_VERSION
+            )));
+        }
+
+        let block_layout_version = read_u32(bytes, 8);
+        if block_layout_version > BLOCK_LAYOUT_VERSION {
+            return Err(invalid_data(format!(
+                "block layout version {} is newer than the supported version {}",
+                block_layout_version, BLOCK_LAYOUT_VERSION
+            )));
+        }
+
+        let mut region = RegionFile {
+            block_layout_version,
+            saved_block_count: read_u32(bytes, 12),
+            block_count: block_count as u32,
+            chunk_area,
+            chunks: vec![None; REGION_AREA],
+        };
+
+        for i in 0..REGION_AREA {
+            let offset = read_u32(bytes, 16 + i * 8) as usize;
+            let length = read_u32(bytes, 16 + i * 8 + 4) as usize;
+            if length == 0 {
+                continue;
+            }
+            let Some(data) = bytes.get(offset..offset + length) else {
+                return Err(invalid_data(format!("chunk {} is outside of the region file", i)));
+            };
+            region.chunks[i] = Some(data.to_vec());
+        }
+
+        return Ok(region);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header: Vec<u8> = Vec::with_capacity(HEADER_SIZE);
+        let mut data: Vec<u8> = vec![];
+
+        header.extend_from_slice(REGION_MAGIC);
+        header.extend_from_slice(&REGION_FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&self.block_layout_version.to_le_bytes());
+        header.extend_from_slice(&self.saved_block_count.to_le_bytes());
+
+        for chunk in self.chunks.iter() {
+            let (offset, length) = match chunk {
+                Some(compressed) => {
+                    let offset = HEADER_SIZE + data.len();
+                    data.extend_from_slice(compressed);
+                    (offset as u32, compressed.len() as u32)
+                }
+                None => (0, 0),
+            };
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&length.to_le_bytes());
+        }
+
+        header.extend_from_slice(&data);
+        return header;
+    }
+
+    pub fn get_chunk(&self, chunk_position: ChunkPos) -> io::Result<Option<ChunkBlocks>> {
+        let Some(compressed) = &self.chunks[get_index_in_region(chunk_position)] else {
+            return Ok(None);
+        };
+
+        let mut data: Vec<u8> = Vec::with_capacity(self.chunk_data_size());
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut data)?;
+        if data.len() != self.chunk_data_size() {
+            return Err(invalid_data(format!(
+                "chunk {} has {} bytes, expected {}",
+                chunk_position,
+                data.len(),
+                self.chunk_data_size()
+            )));
+        }
+
+        let mut blocks: ChunkBlocks = (vec![0; self.chunk_area], vec![0; self.chunk_area]);
+        for (i, block_bytes) in data.chunks_exact(2).enumerate() {
+            let block = u16::from_le_bytes([block_bytes[0], block_bytes[1]]) as usize;
+            if i < self.chunk_area {
+                blocks.0[i] = block;
+            } else {
+                blocks.1[i - self.chunk_area] = block;
+            }
+        }
+
+        migrate_chunk_blocks(self.block_layout_version, self.saved_block_count, self.block_count, &mut blocks);
+        return Ok(Some(blocks));
+    }
+
+    pub fn set_chunk(&mut self, chunk_position: ChunkPos, foreground_blocks: &[usize], background_blocks: &[usize]) -> io::Result<()> {
+        if foreground_blocks.len() != self.chunk_area || background_blocks.len() != self.chunk_area {
+            return Err(invalid_data(format!(
+                "chunk {} doesn't have {} blocks in each layer",
+                chunk_position, self.chunk_area
+            )));
+        }
+
+        // Chunks from older versions are migrated before being saved again,
+        // so they can all be stored with the current block layout.
+        if self.block_layout_version != BLOCK_LAYOUT_VERSION || self.saved_block_count != self.block_count {
+            self.migrate_all_chunks()?;
+        }
+
+        let mut data: Vec<u8> = Vec::with_capacity(self.chunk_data_size());
+        for block in foreground_blocks.iter().chain(background_blocks.iter()) {
+            data.extend_from_slice(&(*block as u16).to_le_bytes());
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        self.chunks[get_index_in_region(chunk_position)] = Some(encoder.finish()?);
+
+        return Ok(());
+    }
+
+    fn migrate_all_chunks(&mut self) -> io::Result<()> {
+        let mut migrated: Vec<(ChunkPos, ChunkBlocks)> = vec![];
+        for i in 0..REGION_AREA {
+            let position = ChunkPos::new(i as i32 % REGION_WIDTH, i as i32 / REGION_WIDTH);
+            if let Some(blocks) = self.get_chunk(position)? {
+                migrated.push((position, blocks));
+            }
+        }
+
+        self.block_layout_version = BLOCK_LAYOUT_VERSION;
+        self.saved_block_count = self.block_count;
+        for (position, (foreground_blocks, background_blocks)) in migrated {
+            self.set_chunk(position, &foreground_blocks, &background_blocks)?;
+        }
+
+        return Ok(());
+    }
+
+    // Two layers of u16s
+    fn chunk_data_size(&self) -> usize {
+        return self.chunk_area * 2 * 2;
+    }
+}
+
+// Brings the blocks of a chunk saved with an older block layout up to date.
+pub fn migrate_chunk_blocks(saved_layout_version: u32, saved_block_count: u32, block_count: u32, blocks: &mut ChunkBlocks) {
+    for migration in BLOCK_LAYOUT_MIGRATIONS.iter().skip(saved_layout_version.saturating_sub(1) as usize) {
+        migration(blocks);
+    }
+
+    // If blocks were removed since this chunk was saved, the ones that
+    // don't exist anymore are turned into air instead of crashing the game.
+    if saved_block_count > block_count {
+        for block in blocks.0.iter_mut().chain(blocks.1.iter_mut()) {
+            if *block >= block_count as usize {
+                *block = 0;
+            }
+        }
+    }
+}
+
+pub fn get_region_position(chunk_position: ChunkPos) -> IVec2 {
+    return chunk_position.0.div_euclid(IVec2::splat(REGION_WIDTH));
+}
+
+fn get_index_in_region(chunk_position: ChunkPos) -> usize {
+    let position = chunk_position.0.rem_euclid(IVec2::splat(REGION_WIDTH));
+    return (position.x + position.y * REGION_WIDTH) as usize;
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+}
+
+fn invalid_data(message: String) -> io::Error {
+    return io::Error::new(ErrorKind::InvalidData, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_COUNT: usize = 9;
+    const CHUNK_AREA: usize = 16 * 16;
+
+    fn test_blocks(seed: usize) -> ChunkBlocks {
+        let mut blocks: ChunkBlocks = (vec![0; CHUNK_AREA], vec![0; CHUNK_AREA]);
+        for i in 0..CHUNK_AREA {
+            blocks.0[i] = (i + seed) % BLOCK_COUNT;
+            blocks.1[i] = (i * 3 + seed) % BLOCK_COUNT;
+        }
+        return blocks;
+    }
+
+    #[test]
+    fn region_round_trip() {
+        let mut region = RegionFile::new(BLOCK_COUNT, CHUNK_AREA);
+        let positions = [ChunkPos::new(0, 0), ChunkPos::new(31, 31), ChunkPos::new(31, 0), ChunkPos::new(5, 20)];
+        for (i, position) in positions.iter().enumerate() {
+            let (foreground, background) = test_blocks(i);
+            region.set_chunk(*position, &foreground, &background).unwrap();
+        }
+
+        let read_back = RegionFile::from_bytes(&region.to_bytes(), BLOCK_COUNT, CHUNK_AREA).unwrap();
+        for (i, position) in positions.iter().enumerate() {
+            assert_eq!(read_back.get_chunk(*position).unwrap(), Some(test_blocks(i)));
+        }
+        assert_eq!(read_back.get_chunk(ChunkPos::new(1, 0)).unwrap(), None);
+    }
+
+    #[test]
+    fn region_file_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("r.0.0.region");
+
+        let mut region = RegionFile::new(BLOCK_COUNT, CHUNK_AREA);
+        let (foreground, background) = test_blocks(7);
+        region.set_chunk(ChunkPos::new(3, 4), &foreground, &background).unwrap();
+        region.write(&path).unwrap();
+
+        let read_back = RegionFile::read(&path, BLOCK_COUNT, CHUNK_AREA).unwrap();
+        assert_eq!(read_back.get_chunk(ChunkPos::new(3, 4)).unwrap(), Some((foreground, background)));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut bytes = RegionFile::new(BLOCK_COUNT, CHUNK_AREA).to_bytes();
+        bytes[4..8].copy_from_slice(&(REGION_FORMAT_VERSION + 1).to_le_bytes());
+        assert!(RegionFile::from_bytes(&bytes, BLOCK_COUNT, CHUNK_AREA).is_err());
+
+        let mut bytes = RegionFile::new(BLOCK_COUNT, CHUNK_AREA).to_bytes();
+        bytes[8..12].copy_from_slice(&(BLOCK_LAYOUT_VERSION + 1).to_le_bytes());
+        assert!(RegionFile::from_bytes(&bytes, BLOCK_COUNT, CHUNK_AREA).is_err());
+
+        assert!(RegionFile::from_bytes(b"not a region", BLOCK_COUNT, CHUNK_AREA).is_err());
+    }
+
+    #[test]
+    fn chunks_of_another_size_fail_to_load() {
+        let mut region = RegionFile::new(BLOCK_COUNT, CHUNK_AREA);
+        let (foreground, background) = test_blocks(1);
+        region.set_chunk(ChunkPos::new(0, 0), &foreground, &background).unwrap();
+        assert!(region.set_chunk(ChunkPos::new(1, 0), &foreground[..25], &background[..25]).is_err());
+
+        let smaller_chunks = RegionFile::from_bytes(&region.to_bytes(), BLOCK_COUNT, 5 * 5).unwrap();
+        assert!(smaller_chunks.get_chunk(ChunkPos::new(0, 0)).is_err());
+    }
+
+    #[test]
+    fn removed_blocks_become_air() {
+        let mut blocks: ChunkBlocks = (vec![BLOCK_COUNT; CHUNK_AREA], vec![1; CHUNK_AREA]);
+        migrate_chunk_blocks(BLOCK_LAYOUT_VERSION, BLOCK_COUNT as u32 + 1, BLOCK_COUNT as u32, &mut blocks);
+        assert_eq!(blocks, (vec![0; CHUNK_AREA], vec![1; CHUNK_AREA]));
+    }
+
+    #[test]
+    fn region_positions() {
+        assert_eq!(get_region_position(ChunkPos::new(0, 31)), ivec2(0, 0));
+        assert_eq!(get_region_position(ChunkPos::new(32, -1)), ivec2(1, -1));
+        assert_eq!(get_region_position(ChunkPos::new(-33, -32)), ivec2(-2, -1));
+        assert_eq!(get_index_in_region(ChunkPos::new(-1, 0)), 31);
+        assert_eq!(get_index_in_region(ChunkPos::new(0, -1)), REGION_AREA - 32);
+    }
+}
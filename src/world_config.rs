@@ -0,0 +1,133 @@
+use std::io::{self, ErrorKind};
+
+use serde::{Deserialize, Serialize};
+
+// Every tile gets at most one quad of 4 vertices, and the meshes index
+// them with u16, so a chunk can't have more than 65536 / 4 tiles. The window
+// is configured so a single draw call can take a mesh this big too.
+pub const MAX_CHUNK_AREA: usize = (u16::MAX as usize + 1) / 4;
+pub const MAX_CHUNK_WIDTH: usize = 128;
+
+const DEFAULT_CHUNK_WIDTH: usize = 16;
+const DEFAULT_TILE_SIZE: usize = 32;
+
+// New worlds are made with these sizes when they are set, existing worlds keep their own
+pub const CHUNK_WIDTH_VARIABLE: &str = "MIJOCRAFT_CHUNK_WIDTH";
+pub const TILE_SIZE_VARIABLE: &str = "MIJOCRAFT_TILE_SIZE";
+
+// The sizes a world is built with. They are saved in the level file, since a
+// world can't be opened with another chunk width than the one it was saved with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorldConfig {
+    // In blocks
+    chunk_width: usize,
+    // In world units
+    tile_size: usize,
+}
+
+impl Default for WorldConfig {
+    fn default() -> WorldConfig {
+        return WorldConfig {
+            chunk_width: DEFAULT_CHUNK_WIDTH,
+            tile_size: DEFAULT_TILE_SIZE,
+        };
+    }
+}
+
+impl WorldConfig {
+    pub fn new(chunk_width: usize, tile_size: usize) -> io::Result<WorldConfig> {
+        let config = WorldConfig { chunk_width, tile_size };
+        config.validate()?;
+        return Ok(config);
+    }
+
+    // The sizes for a new world, from the environment variables. The ones that
+    // aren't set are the defaults, but values that don't work are an error.
+    pub fn from_env() -> io::Result<WorldConfig> {
+        return WorldConfig::from_variables(|name| std::env::var(name).ok());
+    }
+
+    fn from_variables(variable: impl Fn(&str) -> Option<String>) -> io::Result<WorldConfig> {
+        let chunk_width = parse_variable(CHUNK_WIDTH_VARIABLE, variable(CHUNK_WIDTH_VARIABLE), DEFAULT_CHUNK_WIDTH)?;
+        let tile_size = parse_variable(TILE_SIZE_VARIABLE, variable(TILE_SIZE_VARIABLE), DEFAULT_TILE_SIZE)?;
+        return WorldConfig::new(chunk_width, tile_size);
+    }
+
+    // Configs that were deserialized didn't go through new.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.chunk_width == 0 || self.chunk_width > MAX_CHUNK_WIDTH {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "chunk width {} must be between 1 and {}, a chunk can't have more than {} tiles",
+                    self.chunk_width, MAX_CHUNK_WIDTH, MAX_CHUNK_AREA
+                ),
+            ));
+        }
+        if self.tile_size == 0 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "tile size can't be 0"));
+        }
+        return Ok(());
+    }
+
+    pub fn chunk_width(&self) -> usize {
+        return self.chunk_width;
+    }
+
+    pub fn chunk_area(&self) -> usize {
+        return self.chunk_width * self.chunk_width;
+    }
+
+    pub fn tile_size(&self) -> f32 {
+        return self.tile_size as f32;
+    }
+
+    // The width of a chunk in world units.
+    pub fn chunk_world_width(&self) -> f32 {
+        return self.chunk_width as f32 * self.tile_size as f32;
+    }
+}
+
+fn parse_variable(name: &str, value: Option<String>, default: usize) -> io::Result<usize> {
+    let Some(value) = value else {
+        return Ok(default);
+    };
+    return value.trim().parse().map_err(|_| {
+        io::Error::new(ErrorKind::InvalidInput, format!("{} must be a positive number, not \"{}\"", name, value))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_have_to_fit_in_a_mesh() {
+        assert_eq!(MAX_CHUNK_WIDTH * MAX_CHUNK_WIDTH, MAX_CHUNK_AREA);
+        assert!(WorldConfig::new(MAX_CHUNK_WIDTH, 32).is_ok());
+        assert!(WorldConfig::new(MAX_CHUNK_WIDTH + 1, 32).is_err());
+        assert!(WorldConfig::new(0, 32).is_err());
+        assert!(WorldConfig::new(16, 0).is_err());
+        assert!(WorldConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn sizes_from_the_environment() {
+        let variables = |chunk_width: Option<&str>, tile_size: Option<&str>| {
+            return WorldConfig::from_variables(|name| match name {
+                CHUNK_WIDTH_VARIABLE => chunk_width.map(str::to_owned),
+                TILE_SIZE_VARIABLE => tile_size.map(str::to_owned),
+                _ => None,
+            });
+        };
+
+        assert_eq!(variables(None, None).unwrap(), WorldConfig::default());
+        assert_eq!(variables(Some("64"), None).unwrap(), WorldConfig::new(64, DEFAULT_TILE_SIZE).unwrap());
+        assert_eq!(variables(Some(" 8 "), Some("12")).unwrap(), WorldConfig::new(8, 12).unwrap());
+        // Bad values aren't replaced with the defaults
+        assert!(variables(Some("big"), None).is_err());
+        assert!(variables(Some("-16"), None).is_err());
+        assert!(variables(Some("129"), None).is_err());
+        assert!(variables(None, Some("0")).is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+
+use macroquad::prelude::*;
+use noise::{HybridMulti, MultiFractal, NoiseFn, Perlin};
+
+use crate::{
+    biome::{Biome, Decoration, BIOMES},
+    block_registry::{BlockRegistry, AIR},
+    coordinates::{ChunkPos, LocalPos},
+    world_config::WorldConfig,
+};
+
+// How many blocks one unit of noise covers. It used to be the chunk width,
+// but the terrain shouldn't change when the chunks are a different size.
+const TERRAIN_SCALE: f64 = 16.0;
+// Biomes are much wider than hills, this many blocks for one unit of climate noise
+const BIOME_SCALE: f64 = 400.0;
+// The height of a column is blended with the biomes this far away on both sides
+const BIOME_BLEND_RADIUS: i32 = 12;
+// Caves only start this deep, below the first two chunks under the origin of the default size
+const CAVES_BELOW: i32 = -32;
+// How deep the subsurface blocks go under the surface before the stone starts
+const SUBSURFACE_DEPTH: i32 = 25;
+// Everything lower than this that isn't ground is water, and the ground under it is sand
+pub const SEA_LEVEL: i32 = 0;
+// How far the leaves of a tree reach from its trunk, trees right outside
+// of a chunk can still have leaves inside of it
+const TREE_RADIUS: i32 = 2;
+// Trees and cacti can't be closer than this to each other
+const DECORATION_SPACING: i32 = 3;
+// How far from the origin the spawn point is looked for, there's always some land closer than that
+const MAX_SPAWN_DISTANCE: i32 = 4096;
+
+// The block IDs of a biome.
+#[derive(Debug, Clone, Copy)]
+struct BiomeBlocks {
+    surface: usize,
+    subsurface: usize,
+}
+
+// Something that grows on top of a column. Clippy takes the upper case
+// variants, like the ones of every other enum here, for acronyms.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Feature {
+    PLANT(usize),
+    // Both go from the surface up to top, included
+    CACTUS { top: i32 },
+    TREE { top: i32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Column {
+    surface: i32,
+    biome: Biome,
+    feature: Option<Feature>,
+}
+
+// Generates the terrain of the world. The same seed
+// always generates the same blocks for the same chunk.
+pub struct WorldGenerator {
+    config: WorldConfig,
+    seed: u64,
+    terrain_noise: HybridMulti<Perlin>,
+    cave_noise: Perlin,
+    temperature_noise: Perlin,
+    humidity_noise: Perlin,
+    biome_blocks: HashMap<Biome, BiomeBlocks>,
+    stone: usize,
+    sand: usize,
+    water: usize,
+    log: usize,
+    leaves: usize,
+    tall_grass: usize,
+    flower: usize,
+    cactus: usize,
+}
+
+impl WorldGenerator {
+    pub fn new(seed: u64, block_registry: &BlockRegistry, config: WorldConfig) -> WorldGenerator {
+        let block = |name: &str| {
+            block_registry
+                .id_by_name(name)
+                .unwrap_or_else(|| panic!("the world generator needs the block \"{}\"", name))
+        };
+
+        let terrain_noise = HybridMulti::<Perlin>::new(derive_noise_seed(seed, 0))
+            .set_frequency(0.25)
+            .set_lacunarity(3.0);
+
+        let biome_blocks = BIOMES
+            .iter()
+            .map(|biome| {
+                let properties = biome.properties();
+                let blocks = BiomeBlocks {
+                    surface: block(properties.surface_block),
+                    subsurface: block(properties.subsurface_block),
+                };
+                (*biome, blocks)
+            })
+            .collect();
+
+        WorldGenerator {
+            config,
+            seed,
+            terrain_noise,
+            cave_noise: Perlin::new(derive_noise_seed(seed, 1)),
+            temperature_noise: Perlin::new(derive_noise_seed(seed, 2)),
+            humidity_noise: Perlin::new(derive_noise_seed(seed, 3)),
+            biome_blocks,
+            stone: block("stone"),
+            sand: block("sand"),
+            water: block("water"),
+            log: block("log"),
+            leaves: block("leaves"),
+            tall_grass: block("tall_grass"),
+            flower: block("flower"),
+            cactus: block("cactus"),
+        }
+    }
+
+    // The sizes of the world this generates chunks for.
+    pub fn config(&self) -> &WorldConfig {
+        return &self.config;
+    }
+
+    pub fn biome_at(&self, global_x: i32) -> Biome {
+        // Perlin noise is always 0 on whole numbers, so the other coordinate can't be one
+        let x = global_x as f64 / BIOME_SCALE;
+        return Biome::from_climate(self.temperature_noise.get([x, 0.5]), self.humidity_noise.get([x, 0.5]));
+    }
+
+    // The Y position of the surface block at this X position, the decorations on top aren't counted.
+    pub fn surface_height(&self, global_x: i32) -> i32 {
+        let mut base_height = 0.0;
+        let mut height_amplitude = 0.0;
+        let mut samples = 0.0;
+        for offset in -BIOME_BLEND_RADIUS..=BIOME_BLEND_RADIUS {
+            let properties = self.biome_at(global_x + offset).properties();
+            base_height += properties.base_height;
+            height_amplitude += properties.height_amplitude;
+            samples += 1.0;
+        }
+
+        let x = global_x as f64 / TERRAIN_SCALE;
+        let noise = self.terrain_noise.get([x, x, x]);
+        return (base_height / samples + noise * height_amplitude / samples).round() as i32;
+    }
+
+    // Where new players start in world units, standing on dry land close to the
+    // origin, where there isn't a tree or a cactus to get stuck in.
+    pub fn spawn_point(&self) -> Vec2 {
+        let is_clear = |x: i32| {
+            self.surface_height(x) >= SEA_LEVEL
+                && (x - TREE_RADIUS..=x + TREE_RADIUS)
+                    .all(|x| !matches!(self.column(x).feature, Some(Feature::TREE { .. } | Feature::CACTUS { .. })))
+        };
+        let x = (0..MAX_SPAWN_DISTANCE).flat_map(|distance| [distance, -distance]).find(|x| is_clear(*x)).unwrap_or(0);
+        // The player falls the rest of the way
+        return vec2(x as f32 + 0.5, (self.surface_height(x) + 2) as f32) * self.config.tile_size();
+    }
+
+    // The foreground and background blocks of the chunk, chunk_area long each.
+    pub fn generate_chunk(&self, pos: ChunkPos) -> (Vec<usize>, Vec<usize>) {
+        let chunk_width = self.config.chunk_width();
+        let mut foreground_blocks = vec![0; self.config.chunk_area()];
+        let mut background_blocks = vec![0; self.config.chunk_area()];
+
+        let origin = pos.origin(chunk_width).to_ivec2();
+        // With the columns on both sides, for the trees next to the chunk
+        let first_column = origin.x - TREE_RADIUS;
+        let columns: Vec<Column> = (first_column..origin.x + chunk_width as i32 + TREE_RADIUS)
+            .map(|x| self.column(x))
+            .collect();
+
+        for x in 0..chunk_width {
+            let global_x = origin.x + x as i32;
+            let column = columns[(global_x - first_column) as usize];
+            let s = column.surface;
+            let blocks = self.biome_blocks[&column.biome];
+            // The bottom of the water is always sand
+            let surface_block = match s < SEA_LEVEL {
+                true => self.sand,
+                false => blocks.surface,
+            };
+
+            for y in 0..chunk_width {
+                let local = LocalPos::new(x as u32, y as u32, chunk_width);
+                let index = local.index(chunk_width);
+                let global_pos = pos.block(local, chunk_width).to_ivec2();
+
+                if global_pos.y == s {
+                    foreground_blocks[index] = surface_block;
+                    background_blocks[index] = surface_block;
+                } else if global_pos.y < s && global_pos.y >= s - SUBSURFACE_DEPTH {
+                    foreground_blocks[index] = blocks.subsurface;
+                    background_blocks[index] = blocks.subsurface;
+                } else if global_pos.y < s - SUBSURFACE_DEPTH {
+                    foreground_blocks[index] = self.stone;
+                    background_blocks[index] = self.stone;
+                } else if global_pos.y <= SEA_LEVEL {
+                    foreground_blocks[index] = self.water;
+                } else {
+                    let nearby_columns = &columns[x..x + 2 * TREE_RADIUS as usize + 1];
+                    foreground_blocks[index] = self.decoration_at(global_pos.y, nearby_columns);
+                }
+
+                if global_pos.y < CAVES_BELOW {
+                    let sample = self.cave_noise.get([
+                        global_pos.x as f64 / TERRAIN_SCALE,
+                        global_pos.y as f64 / TERRAIN_SCALE,
+                    ]);
+                    if sample >= 0.5 {
+                        foreground_blocks[index] = AIR;
+                    }
+                }
+            }
+        }
+
+        return (foreground_blocks, background_blocks);
+    }
+
+    fn column(&self, global_x: i32) -> Column {
+        let surface = self.surface_height(global_x);
+        let biome = self.biome_at(global_x);
+        return Column {
+            surface,
+            biome,
+            feature: self.feature_at(global_x, surface, biome),
+        };
+    }
+
+    fn feature_at(&self, global_x: i32, surface: i32, biome: Biome) -> Option<Feature> {
+        let properties = biome.properties();
+        // Nothing grows under water
+        if surface < SEA_LEVEL || self.column_random(global_x, 0) >= properties.decoration_chance {
+            return None;
+        }
+        let height = self.column_random(global_x, 1);
+
+        return match properties.decoration {
+            Decoration::NONE => None,
+            Decoration::PLANTS => match height < 0.15 {
+                true => Some(Feature::PLANT(self.flower)),
+                false => Some(Feature::PLANT(self.tall_grass)),
+            },
+            // Only the first of the columns that are too close together gets to grow
+            Decoration::CACTI | Decoration::TREES if self.crowded(global_x, properties.decoration_chance) => None,
+            Decoration::CACTI => Some(Feature::CACTUS {
+                top: surface + 1 + (height * 3.0) as i32,
+            }),
+            Decoration::TREES => Some(Feature::TREE {
+                top: surface + 4 + (height * 3.0) as i32,
+            }),
+        };
+    }
+
+    // If a column on the left would grow something too, without looking further,
+    // so it doesn't depend on the columns before them.
+    fn crowded(&self, global_x: i32, chance: f64) -> bool {
+        return (1..DECORATION_SPACING).any(|offset| self.column_random(global_x - offset, 0) < chance);
+    }
+
+    // The block at this height, from what grows on the column in the middle and the ones around it.
+    fn decoration_at(&self, y: i32, nearby_columns: &[Column]) -> usize {
+        let center = nearby_columns[TREE_RADIUS as usize];
+        match center.feature {
+            Some(Feature::PLANT(plant)) if y == center.surface + 1 => return plant,
+            Some(Feature::CACTUS { top }) if y <= top => return self.cactus,
+            Some(Feature::TREE { top }) if y <= top => return self.log,
+            _ => {}
+        }
+
+        for (i, column) in nearby_columns.iter().enumerate() {
+            let Some(Feature::TREE { top }) = column.feature else {
+                continue;
+            };
+            if tree_has_leaves(i as i32 - TREE_RADIUS, y - top) {
+                return self.leaves;
+            }
+        }
+        return AIR;
+    }
+
+    // The same random number from 0 to 1 every time for the same column, seed and salt.
+    fn column_random(&self, global_x: i32, salt: u64) -> f64 {
+        let hash = derive_noise_seed(self.seed ^ salt.wrapping_mul(0x2545F4914F6CDD1D), global_x as i64 as u64);
+        return hash as f64 / (u32::MAX as f64 + 1.0);
+    }
+}
+
+// The shape of the leaves around the top of the trunk: two rows as wide as
+// the radius, and a narrower one above them.
+fn tree_has_leaves(dx: i32, dy: i32) -> bool {
+    return match dy {
+        -1 | 0 => dx.abs() <= TREE_RADIUS,
+        1 => dx.abs() < TREE_RADIUS,
+        _ => false,
+    };
+}
+
+// The noise functions only take 32 bit seeds, so every one of them
+// gets its own seed mixed from the 64 bit world seed (SplitMix64).
+fn derive_noise_seed(seed: u64, index: u64) -> u32 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    return (z ^ (z >> 32)) as u32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordinates::BlockPos;
+
+    fn test_registry() -> BlockRegistry {
+        return BlockRegistry::load("assets/blocks.ron").unwrap();
+    }
+
+    const TEST_CHUNKS: [ChunkPos; 5] = [
+        ChunkPos(IVec2::new(0, 0)),
+        ChunkPos(IVec2::new(-1, 0)),
+        ChunkPos(IVec2::new(3, -1)),
+        ChunkPos(IVec2::new(-7, -4)),
+        ChunkPos(IVec2::new(12, -10)),
+    ];
+
+    #[test]
+    fn same_seed_generates_same_chunks() {
+        let registry = test_registry();
+        for seed in [0, 1, 42, u64::MAX] {
+            let a = WorldGenerator::new(seed, &registry, WorldConfig::default());
+            let b = WorldGenerator::new(seed, &registry, WorldConfig::default());
+            for pos in TEST_CHUNKS {
+                assert_eq!(a.generate_chunk(pos), b.generate_chunk(pos));
+                // Generating the same chunk twice must not depend on any state either
+                assert_eq!(a.generate_chunk(pos), a.generate_chunk(pos));
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_generate_different_terrain() {
+        let registry = test_registry();
+        let a = WorldGenerator::new(1, &registry, WorldConfig::default());
+        let b = WorldGenerator::new(2, &registry, WorldConfig::default());
+        let differs = (-256..256).any(|x| a.surface_height(x) != b.surface_height(x));
+        assert!(differs);
+    }
+
+    #[test]
+    fn chunks_follow_the_surface() {
+        let registry = test_registry();
+        for chunk_width in [4, 16, 33, 128] {
+            let generator = WorldGenerator::new(7, &registry, WorldConfig::new(chunk_width, 32).unwrap());
+            let s = generator.surface_height(5);
+            let (chunk, local) = BlockPos::new(5, s).split(chunk_width);
+            let (foreground, background) = generator.generate_chunk(chunk);
+            assert_eq!(foreground.len(), chunk_width * chunk_width);
+            let index = local.index(chunk_width);
+            let surface_block = match s < SEA_LEVEL {
+                true => registry.id_by_name("sand").unwrap(),
+                false => registry.id_by_name(generator.biome_at(5).properties().surface_block).unwrap(),
+            };
+            assert_eq!(foreground[index], surface_block);
+            assert_eq!(background[index], surface_block);
+        }
+    }
+
+    #[test]
+    fn every_biome_shows_up() {
+        let registry = test_registry();
+        let generator = WorldGenerator::new(7, &registry, WorldConfig::default());
+        for biome in BIOMES {
+            let found = (-20000..20000).step_by(16).any(|x| generator.biome_at(x) == biome);
+            assert!(found, "no {} in the first 40000 blocks", biome);
+        }
+    }
+
+    #[test]
+    fn biome_borders_have_no_cliffs() {
+        let registry = test_registry();
+        let generator = WorldGenerator::new(7, &registry, WorldConfig::default());
+        let max_amplitude = BIOMES.iter().map(|biome| biome.properties().height_amplitude).fold(0.0, f64::max);
+        let noise_at = |x: i32| {
+            let x = x as f64 / TERRAIN_SCALE;
+            return generator.terrain_noise.get([x, x, x]);
+        };
+
+        let mut borders = 0;
+        for x in -19999..20000 {
+            if generator.biome_at(x) != generator.biome_at(x - 1) {
+                borders += 1;
+            }
+            // The hills can be as steep as in the steepest biome, the blending
+            // between the biomes shouldn't add much more than rounding on top of that
+            let steepest = (noise_at(x) - noise_at(x - 1)).abs() * max_amplitude;
+            let change = (generator.surface_height(x) - generator.surface_height(x - 1)).abs();
+            assert!(change as f64 <= steepest + 2.0, "the height changes by {} at x = {}", change, x);
+        }
+        assert!(borders > 0);
+    }
+
+    #[test]
+    fn oceans_are_filled_up_to_the_sea_level() {
+        let registry = test_registry();
+        let water = registry.id_by_name("water").unwrap();
+        let generator = WorldGenerator::new(7, &registry, WorldConfig::default());
+        let x = (-20000..20000).find(|x| generator.surface_height(*x) < SEA_LEVEL - 2).expect("there is no ocean");
+
+        let s = generator.surface_height(x);
+        for y in s - 1..=SEA_LEVEL + 1 {
+            let (chunk, local) = BlockPos::new(x, y).split(16);
+            let (foreground, background) = generator.generate_chunk(chunk);
+            let index = local.index(16);
+            if y == s {
+                assert_eq!(foreground[index], registry.id_by_name("sand").unwrap());
+            } else if y > s && y <= SEA_LEVEL {
+                assert_eq!(foreground[index], water);
+                assert_eq!(background[index], AIR);
+            } else {
+                assert_ne!(foreground[index], water);
+            }
+        }
+    }
+
+    #[test]
+    fn players_spawn_on_dry_land() {
+        let registry = test_registry();
+        let config = WorldConfig::default();
+        for seed in [0, 7, 1234] {
+            let generator = WorldGenerator::new(seed, &registry, config);
+            let spawn = BlockPos::from_world(generator.spawn_point(), config.tile_size());
+            let surface = generator.surface_height(spawn.x());
+            assert!(surface >= SEA_LEVEL);
+            assert_eq!(spawn.y(), surface + 2);
+            // The player is narrower than a tile, so only this column has to be free
+            for y in surface + 1..=surface + 3 {
+                let (chunk, local) = BlockPos::new(spawn.x(), y).split(16);
+                let block = generator.generate_chunk(chunk).0[local.index(16)];
+                assert!(!registry.get(block).collidable, "{} is in the way at ({}, {})", registry.get(block).name, spawn.x(), y);
+            }
+        }
+    }
+
+    #[test]
+    fn trees_grow_on_the_surface() {
+        let registry = test_registry();
+        let log = registry.id_by_name("log").unwrap();
+        let leaves = registry.id_by_name("leaves").unwrap();
+        let generator = WorldGenerator::new(7, &registry, WorldConfig::default());
+        let x = (-20000..20000)
+            .find(|x| matches!(generator.column(*x).feature, Some(Feature::TREE { .. })))
+            .expect("there are no trees");
+
+        let Some(Feature::TREE { top }) = generator.column(x).feature else {
+            unreachable!();
+        };
+        let block_at = |x: i32, y: i32| {
+            let (chunk, local) = BlockPos::new(x, y).split(16);
+            return generator.generate_chunk(chunk).0[local.index(16)];
+        };
+        let s = generator.surface_height(x);
+        assert!(top - s >= 4);
+        for y in s + 1..=top {
+            assert_eq!(block_at(x, y), log);
+        }
+        assert_eq!(block_at(x, top + 1), leaves);
+        assert_eq!(block_at(x - TREE_RADIUS, top), leaves);
+        assert_eq!(block_at(x + TREE_RADIUS, top), leaves);
+    }
+
+    #[test]
+    fn the_terrain_doesnt_depend_on_the_chunk_width() {
+        let registry = test_registry();
+        let small = WorldGenerator::new(3, &registry, WorldConfig::new(4, 32).unwrap());
+        let large = WorldGenerator::new(3, &registry, WorldConfig::new(32, 32).unwrap());
+        // The large chunk covers 8 by 8 of the small ones
+        let (large_blocks, _) = large.generate_chunk(ChunkPos::new(-1, -2));
+        for small_y in -16..-8 {
+            for small_x in -8..0 {
+                let small_chunk = ChunkPos::new(small_x, small_y);
+                let (small_blocks, _) = small.generate_chunk(small_chunk);
+                for (index, block) in small_blocks.iter().enumerate() {
+                    let position = small_chunk.block(LocalPos::from_index(index, 4), 4);
+                    let large_index = position.local(32).index(32);
+                    assert_eq!(*block, large_blocks[large_index], "at {}", position);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::PathBuf,
+};
+
+use macroquad::prelude::*;
+
+use crate::{
+    coordinates::ChunkPos,
+    region::{get_region_position, ChunkBlocks, RegionFile},
+};
+
+// Saved chunks are grouped into region files inside the "region" folder of the world,
+// see region.rs for how they are laid out.
+pub struct WorldSave {
+    regions_directory: PathBuf,
+    // Regions are kept in memory once opened, so saving
+    // a chunk doesn't need to read its whole region again.
+    regions: HashMap<IVec2, RegionFile>,
+    // Regions that have chunks which were not written to disk yet
+    unsaved_regions: HashSet<IVec2>,
+    // The amount of blocks in the block registry, used to migrate old chunks
+    block_count: usize,
+    // How many blocks are in each layer of a chunk, from the world config
+    chunk_area: usize,
+}
+
+impl WorldSave {
+    pub fn open(world_directory: impl Into<PathBuf>, block_count: usize, chunk_area: usize) -> io::Result<WorldSave> {
+        let regions_directory = world_directory.into().join("region");
+        fs::create_dir_all(&regions_directory)?;
+
+        return Ok(WorldSave {
+            regions_directory,
+            regions: HashMap::new(),
+            unsaved_regions: HashSet::new(),
+            block_count,
+            chunk_area,
+        });
+    }
+
+    // The chunk is only written to disk on the next call to flush.
+    pub fn save_chunk(&mut self, chunk_position: ChunkPos, foreground_blocks: &[usize], background_blocks: &[usize]) -> io::Result<()> {
+        let region_position = get_region_position(chunk_position);
+        let region = self.get_region(region_position)?;
+        region.set_chunk(chunk_position, foreground_blocks, background_blocks)?;
+        self.unsaved_regions.insert(region_position);
+
+        return Ok(());
+    }
+
+    // Returns None if this chunk was never saved, so it has to be generated instead.
+    pub fn load_chunk(&mut self, chunk_position: ChunkPos) -> io::Result<Option<ChunkBlocks>> {
+        let region = self.get_region(get_region_position(chunk_position))?;
+        return region.get_chunk(chunk_position);
+    }
+
+    // Every unsaved region is tried, the ones that fail stay unsaved for the next
+    // flush. Returns the first error if any of them failed.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut first_error = None;
+        for region_position in std::mem::take(&mut self.unsaved_regions) {
+            let path = self.regions_directory.join(region_file_name(region_position));
+            if let Err(e) = self.regions[&region_position].write(&path) {
+                self.unsaved_regions.insert(region_position);
+                first_error.get_or_insert(e);
+            }
+        }
+
+        return match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+    }
+
+    // Forgets the regions that have none of these chunks and nothing left to write,
+    // they are read from the disk again if one of their chunks is needed later.
+    pub fn unload_unused_regions(&mut self, chunks_in_use: impl IntoIterator<Item = ChunkPos>) {
+        let regions_in_use: HashSet<IVec2> = chunks_in_use.into_iter().map(get_region_position).collect();
+        self.regions
+            .retain(|region_position, _| regions_in_use.contains(region_position) || self.unsaved_regions.contains(region_position));
+    }
+
+    fn get_region(&mut self, region_position: IVec2) -> io::Result<&mut RegionFile> {
+        if !self.regions.contains_key(&region_position) {
+            let path = self.regions_directory.join(region_file_name(region_position));
+            let region = if path.exists() {
+                RegionFile::read(&path, self.block_count, self.chunk_area)?
+            } else {
+                RegionFile::new(self.block_count, self.chunk_area)
+            };
+            self.regions.insert(region_position, region);
+        }
+
+        return Ok(self.regions.get_mut(&region_position).unwrap());
+    }
+}
+
+fn region_file_name(region_position: IVec2) -> String {
+    return format!("r.{}.{}.region", region_position.x, region_position.y);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_survive_reopening_the_world() {
+        let directory = tempfile::tempdir().unwrap();
+        let foreground = vec![2; 7 * 7];
+        let mut background = vec![3; 7 * 7];
+        background[17] = 0;
+
+        let mut world_save = WorldSave::open(directory.path(), 9, 7 * 7).unwrap();
+        world_save.save_chunk(ChunkPos::new(-40, 7), &foreground, &background).unwrap();
+        world_save.save_chunk(ChunkPos::new(0, 0), &background, &foreground).unwrap();
+        world_save.flush().unwrap();
+
+        let mut reopened = WorldSave::open(directory.path(), 9, 7 * 7).unwrap();
+        assert_eq!(reopened.load_chunk(ChunkPos::new(-40, 7)).unwrap(), Some((foreground.clone(), background.clone())));
+        assert_eq!(reopened.load_chunk(ChunkPos::new(0, 0)).unwrap(), Some((background, foreground)));
+        assert_eq!(reopened.load_chunk(ChunkPos::new(1, 0)).unwrap(), None);
+    }
+
+    #[test]
+    fn regions_that_fail_to_write_stay_unsaved() {
+        let directory = tempfile::tempdir().unwrap();
+        let blocks = vec![2; 7 * 7];
+        let mut world_save = WorldSave::open(directory.path(), 9, 7 * 7).unwrap();
+        for chunk_position in [ChunkPos::new(0, 0), ChunkPos::new(40, 0), ChunkPos::new(80, 0)] {
+            world_save.save_chunk(chunk_position, &blocks, &blocks).unwrap();
+        }
+
+        // A folder where the region is written first makes writing it fail
+        let blocker = directory.path().join("region").join("r.1.0.tmp");
+        fs::create_dir(&blocker).unwrap();
+        assert!(world_save.flush().is_err());
+        assert_eq!(world_save.unsaved_regions, HashSet::from([ivec2(1, 0)]));
+
+        fs::remove_dir(&blocker).unwrap();
+        world_save.flush().unwrap();
+        let mut reopened = WorldSave::open(directory.path(), 9, 7 * 7).unwrap();
+        assert_eq!(reopened.load_chunk(ChunkPos::new(40, 0)).unwrap(), Some((blocks.clone(), blocks)));
+    }
+
+    #[test]
+    fn only_unused_saved_regions_are_unloaded() {
+        let directory = tempfile::tempdir().unwrap();
+        let blocks = vec![2; 7 * 7];
+        let mut world_save = WorldSave::open(directory.path(), 9, 7 * 7).unwrap();
+        world_save.save_chunk(ChunkPos::new(0, 0), &blocks, &blocks).unwrap();
+        world_save.save_chunk(ChunkPos::new(40, 0), &blocks, &blocks).unwrap();
+        world_save.flush().unwrap();
+        world_save.save_chunk(ChunkPos::new(80, 0), &blocks, &blocks).unwrap();
+
+        world_save.unload_unused_regions([ChunkPos::new(1, 1)]);
+        let mut loaded_regions: Vec<IVec2> = world_save.regions.keys().copied().collect();
+        loaded_regions.sort_by_key(|region| region.x);
+        assert_eq!(loaded_regions, vec![ivec2(0, 0), ivec2(2, 0)]);
+        assert_eq!(world_save.load_chunk(ChunkPos::new(40, 0)).unwrap(), Some((blocks.clone(), blocks)));
+    }
+}
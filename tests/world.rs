@@ -0,0 +1,243 @@
+#![allow(clippy::needless_return)]
+
+// These run without a window, everything here has to work without macroquad's context.
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use macroquad::prelude::*;
+use mijocraft_macroquad::{
+    block_edit::EditHistory,
+    block_registry::BlockRegistry,
+    chunk::ChunkLayer,
+    chunk_manager::{ChunkLoadingConfig, ChunkManager},
+    coordinates::{BlockPos, ChunkPos},
+    player::Player,
+    world_config::WorldConfig,
+    world_generator::WorldGenerator,
+    world_save::WorldSave,
+};
+
+const SEED: u64 = 1234;
+
+// The default sizes, tiny chunks, odd ones, and the biggest ones that fit in a mesh
+fn test_configs() -> Vec<WorldConfig> {
+    return [(16, 32), (4, 32), (33, 10), (128, 8)]
+        .iter()
+        .map(|(chunk_width, tile_size)| WorldConfig::new(*chunk_width, *tile_size).unwrap())
+        .collect();
+}
+
+fn open_world(directory: &Path, config: WorldConfig) -> ChunkManager {
+    let block_registry = Arc::new(BlockRegistry::load("assets/blocks.ron").unwrap());
+    let world_save = WorldSave::open(directory, block_registry.len(), config.chunk_area()).unwrap();
+    return ChunkManager::new(
+        world_save,
+        WorldGenerator::new(SEED, &block_registry, config),
+        block_registry,
+        ChunkLoadingConfig::default(),
+    );
+}
+
+fn surface_height(x: i32) -> i32 {
+    let block_registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+    return WorldGenerator::new(SEED, &block_registry, WorldConfig::default()).surface_height(x);
+}
+
+fn chunk_of(chunk_manager: &ChunkManager, block_position: BlockPos) -> ChunkPos {
+    return block_position.chunk(chunk_manager.config().chunk_width());
+}
+
+fn load_chunks_around(chunk_manager: &mut ChunkManager, block_position: BlockPos) {
+    let center = chunk_of(chunk_manager, block_position);
+    for y in -1..=1 {
+        for x in -1..=1 {
+            chunk_manager.load_chunk_now(center + ivec2(x, y));
+        }
+    }
+}
+
+#[test]
+fn blocks_can_be_changed_in_loaded_chunks_only() {
+    for config in test_configs() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut chunk_manager = open_world(directory.path(), config);
+        let position = BlockPos::new(-3, 40);
+        let far_away = BlockPos::new(10 * config.chunk_width() as i32, 0);
+
+        load_chunks_around(&mut chunk_manager, position);
+        chunk_manager.set_block(position, ChunkLayer::FOREGROUND, 3);
+        chunk_manager.set_block(far_away, ChunkLayer::FOREGROUND, 3);
+        assert_eq!(chunk_manager.get_block(position, ChunkLayer::FOREGROUND), 3);
+        assert_eq!(chunk_manager.get_block(position, ChunkLayer::BACKGROUND), 0);
+        assert_eq!(chunk_manager.get_block(far_away, ChunkLayer::FOREGROUND), 0);
+
+        // Every changed chunk gets new vertices for the renderer
+        let mesh_updates = chunk_manager.take_mesh_updates();
+        assert!(mesh_updates.contains_key(&chunk_of(&chunk_manager, position)));
+        assert!(chunk_manager.take_mesh_updates().is_empty());
+    }
+}
+
+#[test]
+fn light_on_a_chunk_border_remeshes_the_chunk_behind_it() {
+    let directory = tempfile::tempdir().unwrap();
+    let mut chunk_manager = open_world(directory.path(), WorldConfig::default());
+    let torch = chunk_manager.block_registry().id_by_name("torch").unwrap();
+    let emission = chunk_manager.block_registry().get(torch).light_emission as i32;
+    // High up in the sky, the light of the torch ends with 1 on the last tile of the chunk
+    let position = BlockPos::new(16 - emission, 60);
+    load_chunks_around(&mut chunk_manager, position);
+    chunk_manager.take_mesh_updates();
+
+    chunk_manager.set_block(position, ChunkLayer::FOREGROUND, torch);
+    let mesh_updates = chunk_manager.take_mesh_updates();
+    assert!(mesh_updates.contains_key(&chunk_of(&chunk_manager, BlockPos::new(16, 60))));
+}
+
+#[test]
+fn edit_batches_count_the_changed_blocks() {
+    let directory = tempfile::tempdir().unwrap();
+    let mut chunk_manager = open_world(directory.path(), WorldConfig::default());
+    load_chunks_around(&mut chunk_manager, BlockPos::new(0, 40));
+
+    let mut batch = chunk_manager.edit_batch();
+    for x in 0..4 {
+        batch.set_block(BlockPos::new(x, 40), ChunkLayer::BACKGROUND, 5);
+    }
+    // Already planks
+    batch.set_block(BlockPos::new(0, 40), ChunkLayer::BACKGROUND, 5);
+    assert_eq!(batch.finish(), 4);
+}
+
+#[test]
+fn undo_works_after_the_chunk_was_unloaded() {
+    let directory = tempfile::tempdir().unwrap();
+    let mut chunk_manager = open_world(directory.path(), WorldConfig::default());
+    let mut history = EditHistory::new(100);
+    let position = BlockPos::new(5, 40);
+    load_chunks_around(&mut chunk_manager, position);
+
+    let mut batch = chunk_manager.edit_batch();
+    batch.set_block(position, ChunkLayer::FOREGROUND, 4);
+    batch.finish_recorded(&mut history);
+
+    chunk_manager.delete_chunk(chunk_of(&chunk_manager, position));
+    history.undo(&mut chunk_manager);
+    assert!(chunk_manager.is_chunk_loaded(chunk_of(&chunk_manager, position)));
+    assert_eq!(chunk_manager.get_block(position, ChunkLayer::FOREGROUND), 0);
+
+    history.redo(&mut chunk_manager);
+    assert_eq!(chunk_manager.get_block(position, ChunkLayer::FOREGROUND), 4);
+}
+
+#[test]
+fn old_worker_results_dont_replace_a_reloaded_chunk() {
+    let directory = tempfile::tempdir().unwrap();
+    let mut chunk_manager = open_world(directory.path(), WorldConfig::default());
+    let position = BlockPos::new(5, 40);
+    let chunk = chunk_of(&chunk_manager, position);
+
+    // The first request is still on the workers while the chunk is loaded, changed and unloaded
+    chunk_manager.request_chunk(chunk);
+    chunk_manager.load_chunk_now(chunk);
+    chunk_manager.set_block(position, ChunkLayer::FOREGROUND, 4);
+    chunk_manager.delete_chunk(chunk);
+    chunk_manager.request_chunk(chunk);
+
+    let start = Instant::now();
+    while chunk_manager.pending_chunk_positions().next().is_some() {
+        assert!(start.elapsed() < Duration::from_secs(10), "the chunk never finished loading");
+        chunk_manager.integrate_finished_chunks();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(chunk_manager.get_block(position, ChunkLayer::FOREGROUND), 4);
+}
+
+#[test]
+fn changes_survive_reopening_the_world() {
+    for config in test_configs() {
+        let directory = tempfile::tempdir().unwrap();
+        let position = BlockPos::new(-20, -7);
+        {
+            let mut chunk_manager = open_world(directory.path(), config);
+            load_chunks_around(&mut chunk_manager, position);
+            chunk_manager.set_block(position, ChunkLayer::BACKGROUND, 8);
+            chunk_manager.save_modified_chunks();
+        }
+
+        let mut chunk_manager = open_world(directory.path(), config);
+        load_chunks_around(&mut chunk_manager, position);
+        assert_eq!(chunk_manager.get_block(position, ChunkLayer::BACKGROUND), 8);
+    }
+}
+
+#[test]
+fn player_falls_onto_the_ground() {
+    for config in test_configs() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut chunk_manager = open_world(directory.path(), config);
+        let surface = surface_height(0);
+        load_chunks_around(&mut chunk_manager, BlockPos::new(0, surface));
+
+        let tile_size = config.tile_size();
+        let mut player = Player::new(vec2(0.5, surface as f32 + 4.0) * tile_size, tile_size);
+        assert!(player.bounding_box.size.cmpgt(Vec2::ZERO).all());
+        for _ in 0..120 {
+            player.update(&chunk_manager, 1.0 / 60.0);
+        }
+
+        assert!(player.floored);
+        let feet = player.get_position().y - player.bounding_box.size.y * 0.5;
+        assert!((feet - (surface + 1) as f32 * tile_size).abs() < 0.1);
+    }
+}
+
+#[test]
+fn player_doesnt_fall_into_chunks_that_arent_loaded() {
+    for config in test_configs() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut chunk_manager = open_world(directory.path(), config);
+        // Only the chunk of the player is loaded, high up in the sky
+        let chunk = chunk_of(&chunk_manager, BlockPos::new(0, 200));
+        chunk_manager.load_chunk_now(chunk);
+
+        let tile_size = config.tile_size();
+        let mut player = Player::new(vec2(0.5, 200.5) * tile_size, tile_size);
+        // The chunks of 128 are a long way down
+        for _ in 0..600 {
+            player.update(&chunk_manager, 1.0 / 60.0);
+        }
+
+        assert!(player.floored);
+        let feet = player.get_position().y - player.bounding_box.size.y * 0.5;
+        let chunk_bottom = chunk.origin(config.chunk_width()).y() as f32 * tile_size;
+        assert!((feet - chunk_bottom).abs() < 0.1);
+    }
+}
+
+#[test]
+fn the_world_looks_the_same_with_every_chunk_width() {
+    let checked_columns = || (-43..=-37).chain(-3..=3).chain(37..=43);
+    let mut worlds: Vec<ChunkManager> = vec![];
+    let directories: Vec<_> = test_configs().iter().map(|_| tempfile::tempdir().unwrap()).collect();
+    for (config, directory) in test_configs().into_iter().zip(directories.iter()) {
+        let mut chunk_manager = open_world(directory.path(), config);
+        for x in checked_columns() {
+            load_chunks_around(&mut chunk_manager, BlockPos::new(x, surface_height(x)));
+        }
+        worlds.push(chunk_manager);
+    }
+
+    for x in checked_columns() {
+        let surface = surface_height(x);
+        for y in surface - 3..=surface + 3 {
+            let expected = worlds[0].get_block(BlockPos::new(x, y), ChunkLayer::FOREGROUND);
+            for world in worlds.iter() {
+                assert_eq!(world.get_block(BlockPos::new(x, y), ChunkLayer::FOREGROUND), expected, "at ({}, {})", x, y);
+            }
+        }
+    }
+}